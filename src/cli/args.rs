@@ -11,12 +11,37 @@ pub struct Cli {
 pub enum Command {
     /// Install a package from GitHub (or all dependencies from copm.json)
     Install {
-        /// Package specifier (e.g., user/repo). Omit to install all from copm.json.
+        /// Package specifier (e.g., user/repo, user/repo@v1.0.0, user/repo#main:agents).
+        /// Omit to install all from copm.json.
         package: Option<String>,
 
         /// Install globally (~/.copilot/skills/ or ~/.claude/skills/)
         #[arg(short, long)]
         global: bool,
+
+        /// Accept downloads that differ from the integrity in copm.lock and record the new hash
+        #[arg(long)]
+        update_integrity: bool,
+
+        /// Install exactly what copm.lock records; fail if it disagrees with copm.json (for CI)
+        #[arg(long, conflicts_with_all = ["package", "update_integrity", "global"])]
+        frozen: bool,
+
+        /// Install only from the download cache, without network access
+        #[arg(long, env = "COPM_OFFLINE", value_parser = clap::builder::BoolishValueParser::new())]
+        offline: bool,
+
+        /// Replace existing files this package didn't install, keeping a backup in .copm-backup/ (~/.copm/backups/ with -g)
+        #[arg(long, conflicts_with = "skip_existing")]
+        force: bool,
+
+        /// Leave existing files this package didn't install in place
+        #[arg(long)]
+        skip_existing: bool,
+
+        /// Number of packages to download at once when installing from copm.json
+        #[arg(short, long, default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: u16,
     },
 
     /// Uninstall a package
@@ -29,6 +54,29 @@ pub enum Command {
         global: bool,
     },
 
+    /// Update packages to the newest revision allowed by copm.json
+    Update {
+        /// Package names to update. Omit to update all dependencies.
+        packages: Vec<String>,
+    },
+
+    /// Show packages in copm.lock whose upstream has moved
+    Outdated {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Exit with a non-zero status when any package is behind
+        #[arg(long)]
+        exit_code: bool,
+    },
+
+    /// Inspect and manage the download cache (~/.copm/cache)
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
     /// List installed packages
     List {
         /// List globally installed packages
@@ -39,3 +87,25 @@ pub enum Command {
     /// Initialize copm.json in the current directory
     Init,
 }
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// List cached downloads (source, rev, size, last used)
+    List,
+
+    /// Rehash cached trees and report entries that were modified
+    Verify,
+
+    /// Remove cached downloads
+    Clean {
+        /// Only remove entries not used within this age (e.g., 30d, 12h)
+        #[arg(long, value_name = "AGE")]
+        older_than: Option<String>,
+    },
+
+    /// Download a package into the cache without installing it (e.g., owner/repo@v1.0.0)
+    Add {
+        /// Package specifier
+        package: String,
+    },
+}
@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use crate::error::CopmError;
+use crate::fetcher::cache::{now, Cache};
+use crate::fetcher::git::{fetch_package, resolve_package_spec};
+
+/// `copm cache list`: show every cached download.
+pub fn list() -> Result<(), CopmError> {
+    let entries = Cache::open()?.entries()?;
+    if entries.is_empty() {
+        println!("The download cache is empty.");
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 4]> = entries
+        .iter()
+        .map(|e| {
+            [
+                e.meta.source.clone(),
+                short_rev(&e.meta.rev).to_string(),
+                format_size(e.meta.size),
+                format_age(now().saturating_sub(e.meta.last_used)),
+            ]
+        })
+        .collect();
+    let header = ["Source", "Rev", "Size", "Last used"].map(String::from);
+
+    let mut widths = header.clone().map(|h| h.chars().count());
+    for row in &rows {
+        for (w, c) in widths.iter_mut().zip(row) {
+            *w = (*w).max(c.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(c, w)| format!("{c:<w$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+
+    let total: u64 = entries.iter().map(|e| e.meta.size).sum();
+    println!();
+    println!(
+        "{} entr{}, {}",
+        entries.len(),
+        if entries.len() == 1 { "y" } else { "ies" },
+        format_size(total)
+    );
+    Ok(())
+}
+
+/// `copm cache verify`: rehash every entry; modified entries are reported and removed.
+pub fn verify() -> Result<(), CopmError> {
+    let entries = Cache::open()?.entries()?;
+    let mut modified = 0;
+    for e in &entries {
+        let status = if e.verify() {
+            "ok"
+        } else {
+            e.remove()?;
+            modified += 1;
+            "MODIFIED"
+        };
+        println!("{status:<8}  {} ({})", e.meta.source, short_rev(&e.meta.rev));
+    }
+
+    if modified > 0 {
+        return Err(CopmError::CacheCorrupt(modified));
+    }
+    println!(
+        "{} entr{} verified.",
+        entries.len(),
+        if entries.len() == 1 { "y" } else { "ies" }
+    );
+    Ok(())
+}
+
+/// `copm cache clean [--older-than AGE]`: remove everything, or entries not used within AGE.
+pub fn clean(older_than: Option<&str>) -> Result<(), CopmError> {
+    let cache = Cache::open()?;
+    let (removed, freed) = match older_than {
+        None => {
+            let removed = cache.clear()?;
+            (removed.len(), removed.iter().map(|e| e.meta.size).sum())
+        }
+        Some(age) => {
+            let max_age = parse_age(age)?.as_secs();
+            let mut removed = 0;
+            let mut freed = 0;
+            for entry in cache.entries()? {
+                if now().saturating_sub(entry.meta.last_used) > max_age {
+                    entry.remove()?;
+                    removed += 1;
+                    freed += entry.meta.size;
+                }
+            }
+            (removed, freed)
+        }
+    };
+    println!(
+        "Removed {removed} entr{} ({}).",
+        if removed == 1 { "y" } else { "ies" },
+        format_size(freed)
+    );
+    Ok(())
+}
+
+/// `copm cache add <spec>`: download a package into the cache without installing it.
+pub async fn add(package: &str) -> Result<(), CopmError> {
+    let spec = resolve_package_spec(package)?;
+    let tmp_dir = tempfile::tempdir()?;
+    let result = fetch_package(&spec, tmp_dir.path(), false).await?;
+    match result.rev {
+        Some(rev) => println!("Cached {} ({})", spec.source_label(), short_rev(&rev)),
+        None => println!(
+            "Fetched {}, but its commit is unknown so it was not cached",
+            spec.source_label()
+        ),
+    }
+    Ok(())
+}
+
+/// Parse an age like "30d", "12h", "45m", "2w" or "90s".
+pub fn parse_age(s: &str) -> Result<Duration, CopmError> {
+    let invalid = || CopmError::InvalidAge(s.to_string());
+    let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (value, unit) = s.split_at(split);
+    let value: u64 = value.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let total = value.checked_mul(seconds).ok_or_else(invalid)?;
+    Ok(Duration::from_secs(total))
+}
+
+fn short_rev(rev: &str) -> &str {
+    &rev[..rev.len().min(7)]
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// How long ago something was used, e.g. "5m ago".
+pub fn format_age(seconds: u64) -> String {
+    match seconds {
+        s if s < 60 => "just now".to_string(),
+        s if s < 60 * 60 => format!("{}m ago", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h ago", s / (60 * 60)),
+        s => format!("{}d ago", s / (24 * 60 * 60)),
+    }
+}
@@ -1,32 +1,300 @@
-use crate::config::copm_json::CopmJson;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::config::copm_json::{CopmJson, Dependency, UNVERSIONED};
+use crate::config::installed::{InstalledFiles, OwnerRecord};
 use crate::config::lock::{CopmLock, LockedPackage, LockedSource};
 use crate::error::CopmError;
-use crate::fetcher::git::{fetch_package, parse_package_spec};
-use crate::installer;
+use crate::fetcher::cache::Cache;
+use crate::fetcher::git::{
+    check_integrity, fetch_package, list_remote_tags, parse_package_spec, resolve_package_spec,
+    FetchResult, GitRef, PackageSpec,
+};
+use crate::fetcher::version::{resolve_version, version_from_tag};
+use crate::installer::transaction::Transaction;
+use crate::installer::{self, ConflictPolicy};
 use crate::manifest::package_manifest::PackageManifest;
 use crate::paths;
 
-/// Install a single package by specifier (e.g., "owner/repo" or "owner/repo:subpath")
-pub async fn run(package: &str, global: bool) -> Result<(), CopmError> {
-    let (user, repo, sub_path) = parse_package_spec(package)?;
-    println!("Fetching {user}/{repo}...");
+/// Options that apply to every package installed by one `copm install` invocation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InstallOptions {
+    /// Install globally
+    pub global: bool,
+    /// Accept content that no longer matches the integrity recorded in copm.lock
+    pub update_integrity: bool,
+    /// Install exactly what copm.lock records and never write copm.json/copm.lock
+    pub frozen: bool,
+    /// Resolve every package from the download cache without touching the network
+    pub offline: bool,
+    /// How to treat destinations the package doesn't own
+    pub conflicts: ConflictPolicy,
+}
+
+/// Install a single package by specifier (e.g., "owner/repo", "owner/repo@v1.0.0" or "owner/repo:subpath")
+pub async fn run(package: &str, opts: InstallOptions) -> Result<(), CopmError> {
+    let spec = resolve_package_spec(package)?;
+    let mut tx = Transaction::new();
+    install_spec(&spec, None, opts, &mut tx).await?;
+    tx.commit();
+    Ok(())
+}
+
+/// Install `spec`. When `locked` is given, its commit is fetched instead of resolving the
+/// spec's ref again and the download is checked against its integrity; the spec is still
+/// what gets recorded in copm.json.
+///
+/// Every change, including to copm.json and copm.lock, is made within `tx`.
+///
+/// Returns the lock entry describing what was installed.
+pub(crate) async fn install_spec(
+    spec: &PackageSpec,
+    locked: Option<&LockedPackage>,
+    opts: InstallOptions,
+    tx: &mut Transaction,
+) -> Result<LockedPackage, CopmError> {
+    let planned = plan(spec, locked.cloned(), opts).await?;
+    let fetched = fetch(planned.fetch_spec.clone(), planned.fetch_message(), opts.offline).await?;
+    install_fetched(&planned, &fetched.result, opts, tx)
+}
+
+/// A package whose version has been resolved, ready to be fetched and installed.
+struct Planned {
+    /// The spec as requested (recorded in copm.json)
+    spec: PackageSpec,
+    locked: Option<LockedPackage>,
+    /// What to download: the spec pinned to the locked commit or the resolved tag
+    fetch_spec: PackageSpec,
+    /// Concrete version being installed
+    version: String,
+}
+
+impl Planned {
+    fn fetch_message(&self) -> String {
+        let source_label = self.spec.source_label();
+        match (self.locked.as_ref().and_then(|p| p.source.rev.as_deref()), &self.spec.git_ref) {
+            (Some(rev), _) => format!("Fetching {source_label} (locked at {rev})..."),
+            (None, Some(r)) => format!("Fetching {source_label}{r}..."),
+            (None, None) => format!("Fetching {source_label}..."),
+        }
+    }
+}
+
+/// Packages that share one download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchGroup {
+    /// What to download. Keeps a `sub_path` only when every member wants the same one.
+    pub spec: PackageSpec,
+    /// Indexes of the packages served by this download
+    pub members: Vec<usize>,
+}
+
+/// Group packages by what they download (source, ref or locked commit, and pinned
+/// integrity) so that each distinct download happens once, e.g. for several subpaths
+/// of one collection. Groups are in order of their first member.
+pub fn group_fetches(specs: &[PackageSpec]) -> Vec<FetchGroup> {
+    let key = |spec: &PackageSpec| (spec.source_label(), spec.git_ref.clone(), spec.integrity.clone());
+    let mut groups: Vec<FetchGroup> = Vec::new();
+    for (index, spec) in specs.iter().enumerate() {
+        match groups.iter_mut().find(|g| key(&g.spec) == key(spec)) {
+            Some(group) => {
+                // Different subpaths of one download need the whole tree
+                if group.spec.sub_path != spec.sub_path {
+                    group.spec.sub_path = None;
+                }
+                group.members.push(index);
+            }
+            None => groups.push(FetchGroup {
+                spec: spec.clone(),
+                members: vec![index],
+            }),
+        }
+    }
+    groups
+}
+
+/// Offline, undo the merging of different subpaths wherever the whole tree isn't cached:
+/// each subpath may still be cached on its own (see [`crate::fetcher::cache::partial_source`]).
+pub fn split_uncached(groups: Vec<FetchGroup>, specs: &[PackageSpec], cache: &Cache) -> Vec<FetchGroup> {
+    let mut split = Vec::with_capacity(groups.len());
+    for group in groups {
+        let merged = group.spec.sub_path.is_none() && group.members.iter().any(|&m| specs[m].sub_path.is_some());
+        let cached = match &group.spec.git_ref {
+            Some(GitRef::Rev(rev)) => cache.contains(&group.spec.source_label(), rev),
+            _ => false,
+        };
+        if merged && !cached {
+            split.extend(group.members.iter().map(|&m| FetchGroup {
+                spec: specs[m].clone(),
+                members: vec![m],
+            }));
+        } else {
+            split.push(group);
+        }
+    }
+    split
+}
+
+/// A download, kept alive for as long as its extracted tree is in use.
+struct Fetched {
+    result: FetchResult,
+    _tmp_dir: tempfile::TempDir,
+}
+
+/// Resolve what to fetch for `spec`: the locked commit, the highest tag matching a
+/// version range, or the spec's own ref.
+async fn plan(
+    spec: &PackageSpec,
+    locked: Option<LockedPackage>,
+    opts: InstallOptions,
+) -> Result<Planned, CopmError> {
+    let source_label = spec.source_label();
+    let locked_rev = locked.as_ref().and_then(|p| p.source.rev.as_deref());
+
+    // Resolve a version range to the highest matching tag (the lock already pins one)
+    let resolved = match (&spec.version_req, locked_rev) {
+        (Some(_), None) if opts.offline => {
+            return Err(CopmError::NotCached(spec.to_string()));
+        }
+        (Some(req), None) => {
+            let tags = list_remote_tags(spec).await?;
+            let resolved = resolve_version(&tags, req).ok_or_else(|| CopmError::NoMatchingVersion {
+                pkg: source_label.clone(),
+                req: req.to_string(),
+            })?;
+            println!("Resolved {source_label}@{req} → {}", resolved.tag);
+            Some(resolved)
+        }
+        _ => None,
+    };
+
+    let fetch_spec = match (locked_rev, &resolved) {
+        (Some(rev), _) => PackageSpec {
+            git_ref: Some(GitRef::Rev(rev.to_string())),
+            ..spec.clone()
+        },
+        (None, Some(r)) => PackageSpec {
+            git_ref: Some(GitRef::Tag(r.tag.clone())),
+            ..spec.clone()
+        },
+        (None, None) => spec.clone(),
+    };
+
+    // Concrete version being installed
+    let version = if let Some(p) = &locked {
+        p.version.clone()
+    } else if let Some(r) = &resolved {
+        r.version.to_string()
+    } else if let Some(GitRef::Tag(tag)) = &spec.git_ref {
+        version_from_tag(tag).map_or_else(|| UNVERSIONED.to_string(), |v| v.to_string())
+    } else {
+        UNVERSIONED.to_string()
+    };
+
+    Ok(Planned {
+        spec: spec.clone(),
+        locked,
+        fetch_spec,
+        version,
+    })
+}
+
+/// Download `spec` to a temporary directory. Takes everything by value so that it can
+/// run as a separate task.
+async fn fetch(spec: PackageSpec, message: String, offline: bool) -> Result<Fetched, CopmError> {
+    println!("{message}");
+    let tmp_dir = tempfile::tempdir()?;
+    let result = fetch_package(&spec, tmp_dir.path(), offline).await?;
+    Ok(Fetched {
+        result,
+        _tmp_dir: tmp_dir,
+    })
+}
+
+/// Check a download of `spec` against its entry in copm.lock and return the integrity
+/// to record for it.
+///
+/// A hash of another kind than the locked one (the tarball download fell back to a git
+/// clone, or the other way round) can't be compared: the download is then accepted
+/// only if it's the locked commit, and the lock keeps its original integrity. Under
+/// `--frozen` that is an error, since nothing checks the content itself.
+pub fn verify_integrity(
+    spec: &PackageSpec,
+    locked: Option<&LockedPackage>,
+    result: &FetchResult,
+    opts: InstallOptions,
+) -> Result<String, CopmError> {
+    let Some(locked) = locked else {
+        return Ok(result.integrity.clone());
+    };
+    let Some(expected) = locked.integrity.as_deref() else {
+        return Ok(result.integrity.clone());
+    };
+    let unverifiable = || CopmError::IntegrityUnverifiable {
+        pkg: spec.source_label(),
+        expected: expected.to_string(),
+        actual: result.integrity.clone(),
+    };
+    match check_integrity(expected, &result.integrity) {
+        Some(true) => Ok(result.integrity.clone()),
+        // A local directory is expected to change while it's being developed
+        Some(false) if opts.update_integrity || (spec.path.is_some() && !opts.frozen) => {
+            println!("Updating integrity: {expected} → {}", result.integrity);
+            Ok(result.integrity.clone())
+        }
+        Some(false) => Err(CopmError::IntegrityMismatch {
+            pkg: spec.source_label(),
+            expected: expected.to_string(),
+            actual: result.integrity.clone(),
+        }),
+        None if opts.frozen => Err(unverifiable()),
+        None if opts.update_integrity => {
+            println!("Updating integrity: {expected} → {}", result.integrity);
+            Ok(result.integrity.clone())
+        }
+        None if result.rev.is_some() && result.rev == locked.source.rev => {
+            println!(
+                "Download produced {} instead of {expected}; accepted as the locked commit",
+                result.integrity
+            );
+            Ok(expected.to_string())
+        }
+        None => Err(unverifiable()),
+    }
+}
+
+/// Check a download against copm.lock, install the planned package's targets from it
+/// and record the package in copm.json and copm.lock, all within `tx`.
+fn install_fetched(
+    planned: &Planned,
+    result: &FetchResult,
+    opts: InstallOptions,
+    tx: &mut Transaction,
+) -> Result<LockedPackage, CopmError> {
+    let global = opts.global;
+    let spec = &planned.spec;
+    let PackageSpec { repo, git_ref, sub_path, .. } = spec.clone();
+    let source_label = spec.source_label();
 
     // Load tools config (default to copilot if no copm.json)
     let copm_json_path = paths::copm_json_path();
     let config = CopmJson::load_or_default(&copm_json_path);
     let tools = &config.tools;
 
-    // Download to temp directory
-    let tmp_dir = tempfile::tempdir()?;
-    let result = fetch_package(&user, &repo, tmp_dir.path()).await?;
+    // Verify the download against copm.lock
+    let integrity = verify_integrity(spec, planned.locked.as_ref(), result, opts)?;
 
     // Detect manifest
-    let source_label = format!("{user}/{repo}");
-    let manifest = PackageManifest::detect_from_dir(
+    let mut manifest = PackageManifest::detect_from_dir(
         &result.extracted_dir,
         sub_path.as_deref(),
         &source_label,
     )?;
+    manifest.version = planned.version.clone();
 
     // Derive a clean package name from repo + optional sub_path
     let pkg_name = package_name(&repo, sub_path.as_deref());
@@ -35,9 +303,19 @@ pub async fn run(package: &str, global: bool) -> Result<(), CopmError> {
         println!("  [{}] path={}", t.target_type, t.path);
     }
 
-    // Install all targets
-    let (installed_paths, target_types) =
-        installer::install_targets(&result.extracted_dir, &manifest, &pkg_name, tools, global)?;
+    // Install all targets, checking destinations against the recorded owners
+    let record = OwnerRecord::locate(global, Path::new("."), &paths::installed_files_path()?)?;
+    let owners = record.owners()?;
+    let (installed_paths, target_types) = installer::install_targets(
+        &result.extracted_dir,
+        &manifest,
+        &pkg_name,
+        tools,
+        global,
+        &owners,
+        opts.conflicts,
+        tx,
+    )?;
 
     let installed_files: Vec<String> = installed_paths
         .iter()
@@ -49,36 +327,64 @@ pub async fn run(package: &str, global: bool) -> Result<(), CopmError> {
     }
     println!("Installed {pkg_name}");
 
+    let locked_pkg = LockedPackage {
+        name: pkg_name.clone(),
+        version: manifest.version.clone(),
+        source: LockedSource {
+            source_type: spec.kind.to_string(),
+            repo: source_label.clone(),
+            host: spec.host.clone(),
+            url: spec.url.clone(),
+            git_ref: git_ref.clone(),
+            rev: result.rev.clone(),
+            sub_path: sub_path.clone(),
+        },
+        integrity: Some(integrity),
+        targets: target_types,
+        installed_files,
+    };
+
+    // Without a copm.lock, remember in ~/.copm which files this package owns
+    if let OwnerRecord::Installed { path, scope } = &record {
+        tx.track(path)?;
+        let mut installed = InstalledFiles::load(path)?;
+        installed.upsert_package(scope, locked_pkg.clone());
+        installed.save(path)?;
+    }
+
     // Update copm.json and copm.lock (only for local installs with existing copm.json)
-    if !global && copm_json_path.exists() {
+    if !global && !opts.frozen && copm_json_path.exists() {
         let mut config = CopmJson::load(&copm_json_path)?;
-        config.add_dependency(&pkg_name, &source_label, &manifest.version, sub_path.clone());
-        config.save(&copm_json_path)?;
-
+        // copm.json keeps the range; copm.lock gets the concrete version
+        let dep_version = spec
+            .version_req
+            .as_ref()
+            .map_or_else(|| manifest.version.clone(), |req| req.to_string());
+        config.add_dependency(
+            &pkg_name,
+            &source_label,
+            &dep_version,
+            git_ref,
+            sub_path,
+        );
         let lock_path = paths::copm_lock_path();
         let mut lock = CopmLock::load(&lock_path)?;
-        lock.upsert_package(LockedPackage {
-            name: pkg_name.clone(),
-            version: manifest.version.clone(),
-            source: LockedSource {
-                source_type: "github".to_string(),
-                repo: source_label,
-                rev: None,
-                sub_path,
-            },
-            integrity: Some(result.integrity),
-            targets: target_types,
-            installed_files,
-        });
+        tx.track(&copm_json_path)?;
+        tx.track(&lock_path)?;
+        config.save(&copm_json_path)?;
+        lock.upsert_package(locked_pkg.clone());
         lock.save(&lock_path)?;
         println!("Updated copm.json and copm.lock");
     }
 
-    Ok(())
+    Ok(locked_pkg)
 }
 
-/// Install all dependencies from copm.json
-pub async fn run_all() -> Result<(), CopmError> {
+/// Install all dependencies from copm.json, downloading up to `jobs` packages at once.
+///
+/// Every package is installed in one transaction: if any of them fails, the files and
+/// copm.json/copm.lock are restored to how they were before.
+pub async fn run_all(opts: InstallOptions, jobs: usize) -> Result<(), CopmError> {
     let copm_json_path = paths::copm_json_path();
     let config = CopmJson::load(&copm_json_path)?;
 
@@ -87,26 +393,182 @@ pub async fn run_all() -> Result<(), CopmError> {
         return Ok(());
     }
 
+    let lock = CopmLock::load(&paths::copm_lock_path())?;
+
+    if opts.frozen {
+        let drift = lock.drift_from(&config);
+        if !drift.is_empty() {
+            let list = drift.iter().map(|d| format!("  {d}")).collect::<Vec<_>>().join("\n");
+            return Err(CopmError::LockOutOfDate(list));
+        }
+    }
+
+    if opts.offline {
+        let missing = uncached_dependencies(&config, &lock)?;
+        if !missing.is_empty() {
+            let list = missing.iter().map(|m| format!("  {m}")).collect::<Vec<_>>().join("\n");
+            return Err(CopmError::OfflineUnavailable(list));
+        }
+    }
+
     let count = config.dependencies.len();
     println!("Installing {count} package(s) from copm.json...");
 
-    for (name, dep) in &config.dependencies {
+    let opts = InstallOptions { global: false, ..opts };
+    let mut failed = 0;
+
+    // Resolve every dependency first (version ranges concurrently) so that packages
+    // sharing a repository and ref (e.g. several subpaths of one collection) can share
+    // a single download
+    let deps: Vec<_> = config
+        .dependencies
+        .iter()
+        .map(|(name, dep)| (name.clone(), dependency_spec(dep), locked_package(&lock, name, dep).cloned()))
+        .collect();
+    let plans = map_concurrently(deps, jobs, move |(name, spec, locked)| async move {
+        let planned = match spec {
+            Ok(spec) => plan(&spec, locked, opts).await,
+            Err(e) => Err(e),
+        };
+        (name, planned)
+    })
+    .await;
+    let mut planned = Vec::new();
+    for (name, result) in plans {
+        match result {
+            Ok(p) => planned.push((name, p)),
+            Err(e) => {
+                eprintln!("Failed to install {name}: {e}");
+                failed += 1;
+            }
+        }
+    }
+
+    // Download each distinct source once, up to `jobs` at a time
+    let fetch_specs: Vec<PackageSpec> = planned.iter().map(|(_, p)| p.fetch_spec.clone()).collect();
+    let mut groups = group_fetches(&fetch_specs);
+    if opts.offline {
+        groups = split_uncached(groups, &fetch_specs, &Cache::open()?);
+    }
+    let mut download_of = vec![0; planned.len()];
+    for (index, group) in groups.iter().enumerate() {
+        for &member in &group.members {
+            download_of[member] = index;
+        }
+    }
+    let fetches: Vec<_> = groups
+        .into_iter()
+        .map(|group| {
+            let message = planned[group.members[0]].1.fetch_message();
+            (group.spec, message)
+        })
+        .collect();
+    let downloads = map_concurrently(fetches, jobs, move |(spec, message)| async move {
+        fetch(spec, message, opts.offline).await.map_err(|e| e.to_string())
+    })
+    .await;
+
+    // Install in copm.json order so that output and copm.lock don't depend on which
+    // download finished first
+    let mut tx = Transaction::new();
+    for ((name, p), index) in planned.iter().zip(download_of) {
         println!();
-        // Reconstruct the original specifier
-        let spec = match &dep.sub_path {
-            Some(sp) => format!("{}:{sp}", dep.source),
-            None => dep.source.clone(),
+        let result = match &downloads[index] {
+            Ok(fetched) => install_fetched(p, &fetched.result, opts, &mut tx).map_err(|e| e.to_string()),
+            Err(e) => Err(e.clone()),
         };
-        if let Err(e) = run(&spec, false).await {
+        if let Err(e) = result {
             eprintln!("Failed to install {name}: {e}");
+            failed += 1;
         }
     }
 
     println!();
+    if failed > 0 {
+        tx.rollback();
+        println!("Rolled back all changes.");
+        return Err(CopmError::InstallFailed(failed));
+    }
+    tx.commit();
     println!("Done.");
     Ok(())
 }
 
+/// Run `f` on every item, at most `jobs` at a time, and return the results in the
+/// order of `items` regardless of which task finishes first.
+pub async fn map_concurrently<T, R, F, Fut>(items: Vec<T>, jobs: usize, f: F) -> Vec<R>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = R> + Send + 'static,
+    R: Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+    let mut tasks = JoinSet::new();
+    for (index, item) in items.into_iter().enumerate() {
+        let semaphore = Arc::clone(&semaphore);
+        let task = f(item);
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            (index, task.await)
+        });
+    }
+    let mut results = Vec::with_capacity(tasks.len());
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok(result) => results.push(result),
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Reconstruct the package specifier recorded for a copm.json dependency. The recorded
+/// source already names its host, so the current user's `default_host` is not applied.
+pub fn dependency_spec(dep: &Dependency) -> Result<PackageSpec, CopmError> {
+    let mut spec = parse_package_spec(&dep.source)?;
+    spec.git_ref = dep.git_ref.clone();
+    spec.version_req = dep.version_req()?;
+    spec.sub_path = dep.sub_path.clone();
+    spec.integrity = dep.integrity.clone();
+    Ok(spec)
+}
+
+/// The copm.lock entry for `name`, provided it has a recorded commit and still
+/// describes the same source, ref, version range and sub_path as the copm.json dependency.
+fn locked_package<'a>(lock: &'a CopmLock, name: &str, dep: &Dependency) -> Option<&'a LockedPackage> {
+    lock.packages
+        .iter()
+        .find(|p| p.name == name && p.matches_dependency(dep))
+}
+
+/// Dependencies that can't be installed offline: not pinned in copm.lock, or
+/// pinned to a commit that is not in the download cache. Local directories are
+/// always available.
+fn uncached_dependencies(config: &CopmJson, lock: &CopmLock) -> Result<Vec<String>, CopmError> {
+    let cache = Cache::open()?;
+    let mut missing = Vec::new();
+    for (name, dep) in &config.dependencies {
+        if dependency_spec(dep).is_ok_and(|spec| spec.path.is_some()) {
+            continue;
+        }
+        match locked_package(lock, name, dep) {
+            None => missing.push(format!("{name} (not pinned in copm.lock)")),
+            Some(pkg) if !pkg.kind().has_commits() => {
+                missing.push(format!("{name} ({} is not cached)", pkg.source.repo));
+            }
+            Some(pkg) => {
+                let rev = pkg.source.rev.as_deref().unwrap_or_default();
+                let sub_path = pkg.source.sub_path.as_deref();
+                if !cache.contains_with_subpath(&pkg.source.repo, sub_path, rev) {
+                    missing.push(format!("{name} ({}@{rev})", pkg.source.repo));
+                }
+            }
+        }
+    }
+    Ok(missing)
+}
+
 /// Derive a package name from repo + optional sub_path.
 /// "awesome-copilot" + Some("agents")                        → "awesome-copilot-agents"
 /// "awesome-copilot" + Some("prompts/update-llms.prompt.md") → "awesome-copilot-update-llms"
@@ -1,19 +1,93 @@
+pub mod cache;
 pub mod init;
 pub mod install;
 pub mod list;
+pub mod outdated;
 pub mod uninstall;
+pub mod update;
 
-use crate::cli::args::Command;
+use crate::cli::args::{CacheCommand, Command};
+use crate::config::process_lock::ProcessLock;
 use crate::error::CopmError;
+use crate::installer::ConflictPolicy;
+use crate::paths;
 
 pub async fn dispatch(command: Command) -> Result<(), CopmError> {
+    // Commands that change copm.json, copm.lock, installed files or the cache run one at a time
+    let _locks = process_locks(&command)?;
+
     match command {
-        Command::Install { package, global } => match package {
-            Some(pkg) => install::run(&pkg, global).await,
-            None => install::run_all().await,
-        },
+        Command::Install {
+            package,
+            global,
+            update_integrity,
+            frozen,
+            offline,
+            force,
+            skip_existing,
+            jobs,
+        } => {
+            let conflicts = if force {
+                ConflictPolicy::Force
+            } else if skip_existing {
+                ConflictPolicy::SkipExisting
+            } else {
+                ConflictPolicy::Fail
+            };
+            let opts =
+                install::InstallOptions { global, update_integrity, frozen, offline, conflicts };
+            match package {
+                Some(pkg) => install::run(&pkg, opts).await,
+                None => install::run_all(opts, jobs.into()).await,
+            }
+        }
         Command::Uninstall { package, global } => uninstall::run(&package, global),
+        Command::Update { packages } => update::run(&packages).await,
+        Command::Outdated { json, exit_code } => outdated::run(json, exit_code).await,
+        Command::Cache { command } => match command {
+            CacheCommand::List => cache::list(),
+            CacheCommand::Verify => cache::verify(),
+            CacheCommand::Clean { older_than } => cache::clean(older_than.as_deref()),
+            CacheCommand::Add { package } => cache::add(&package).await,
+        },
         Command::List { global } => list::run(global),
         Command::Init => init::run(),
     }
 }
+
+/// The locks a command holds while it runs. Project installs also share the ~/.copm
+/// lock so that the cache they copy from isn't cleaned underneath them.
+fn process_locks(command: &Command) -> Result<Vec<ProcessLock>, CopmError> {
+    Ok(match command {
+        Command::Install { global: true, .. } | Command::Uninstall { global: true, .. } => {
+            vec![ProcessLock::home()?]
+        }
+        // Without copm.json or copm.lock, owners are recorded in ~/.copm/installed.json
+        Command::Install { package: Some(_), .. } | Command::Uninstall { .. } if !has_project_files() => {
+            vec![ProcessLock::project()?, ProcessLock::home()?]
+        }
+        Command::Install { .. } | Command::Update { .. } => {
+            vec![ProcessLock::project()?, ProcessLock::home_shared()?]
+        }
+        Command::Uninstall { .. } | Command::Init => vec![ProcessLock::project()?],
+        Command::Cache {
+            command: CacheCommand::Clean { .. } | CacheCommand::Add { .. } | CacheCommand::Verify,
+        } => vec![ProcessLock::home()?],
+        Command::Cache { command: CacheCommand::List }
+        | Command::Outdated { .. }
+        | Command::List { .. } => Vec::new(),
+    })
+}
+
+fn has_project_files() -> bool {
+    paths::copm_json_path().exists() || paths::copm_lock_path().exists()
+}
+
+/// Human-readable revision: "1.4.0 (3f2c1ab)", or just the short commit when unversioned.
+pub(crate) fn format_revision(version: Option<&str>, rev: Option<&str>) -> String {
+    let rev = rev.map(|r| &r[..r.len().min(7)]).unwrap_or("unknown");
+    match version {
+        Some(v) => format!("{v} ({rev})"),
+        None => rev.to_string(),
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use semver::VersionReq;
+use serde::Serialize;
+
+use crate::commands::format_revision;
+use crate::config::copm_json::{CopmJson, UNVERSIONED};
+use crate::config::lock::{CopmLock, LockedPackage, LockedSource};
+use crate::error::CopmError;
+use crate::fetcher::git::{list_remote_refs, GitRef, RemoteRefs};
+use crate::fetcher::version::{resolve_version, version_from_tag};
+use crate::paths;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Revision {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub rev: String,
+}
+
+impl Revision {
+    fn same_commit(&self, other: &Revision) -> bool {
+        self.rev.starts_with(&other.rev) || other.rev.starts_with(&self.rev)
+    }
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_revision(self.version.as_deref(), Some(&self.rev)))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Row {
+    pub name: String,
+    pub locked: Option<Revision>,
+    /// Newest revision allowed by copm.json (what `copm update` would install)
+    pub wanted: Option<Revision>,
+    /// Newest release tag, or the default branch when there are no version tags.
+    /// Shown for information only: it may be outside what copm.json allows.
+    pub latest: Option<Revision>,
+    /// Whether `copm update` would install a different commit than the locked one
+    pub outdated: bool,
+    /// Why upstream couldn't be queried
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Compare every package in copm.lock with its upstream repository.
+///
+/// With `exit_code`, returns an error when at least one package is behind or its
+/// upstream couldn't be queried.
+pub async fn run(json: bool, exit_code: bool) -> Result<(), CopmError> {
+    let lock = CopmLock::load(&paths::copm_lock_path())?;
+    let config = CopmJson::load_or_default(&paths::copm_json_path());
+
+    let mut remotes: HashMap<String, Result<RemoteRefs, String>> = HashMap::new();
+    let mut rows = Vec::new();
+    // Local directories and archive downloads have no upstream refs to compare with
+    for pkg in lock.packages.iter().filter(|p| p.kind().has_commits()) {
+        if !remotes.contains_key(&pkg.source.repo) {
+            let refs = query(&pkg.source).await.map_err(|e| e.to_string());
+            if let Err(e) = &refs {
+                eprintln!("Failed to query {}: {e}", pkg.source.repo);
+            }
+            remotes.insert(pkg.source.repo.clone(), refs);
+        }
+
+        let version_req = config
+            .dependencies
+            .get(&pkg.name)
+            .and_then(|dep| dep.version_req().ok().flatten());
+        let refs = remotes[&pkg.source.repo].as_ref().map_err(String::as_str);
+        rows.push(compare(pkg, version_req.as_ref(), refs));
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+    } else if rows.is_empty() {
+        println!("No packages in copm.lock.");
+    } else {
+        print_table(&rows);
+    }
+
+    let failed = rows.iter().filter(|r| r.error.is_some()).count();
+    let outdated = rows.iter().filter(|r| r.outdated).count();
+    if exit_code && failed > 0 {
+        return Err(CopmError::OutdatedUnknown(failed));
+    }
+    if exit_code && outdated > 0 {
+        return Err(CopmError::Outdated(outdated));
+    }
+    Ok(())
+}
+
+async fn query(source: &LockedSource) -> Result<RemoteRefs, CopmError> {
+    let spec = source.spec()?;
+    list_remote_refs(&spec).await
+}
+
+/// Compare `pkg` with the refs of its upstream repository (or the error querying them).
+/// A package is outdated when the locked commit differs from the wanted one; newer
+/// releases outside its ref or version range don't count.
+pub fn compare(
+    pkg: &LockedPackage,
+    version_req: Option<&VersionReq>,
+    refs: Result<&RemoteRefs, &str>,
+) -> Row {
+    let locked = pkg.source.rev.as_ref().map(|rev| Revision {
+        version: (pkg.version != UNVERSIONED).then(|| pkg.version.clone()),
+        rev: rev.clone(),
+    });
+
+    let (wanted, latest) = match refs {
+        Ok(refs) => (
+            wanted(refs, pkg.source.git_ref.as_ref(), version_req),
+            latest(refs),
+        ),
+        Err(_) => (None, None),
+    };
+
+    let outdated = match (&locked, &wanted) {
+        (Some(l), Some(w)) => !l.same_commit(w),
+        (None, Some(_)) => true,
+        _ => false,
+    };
+
+    Row {
+        name: pkg.name.clone(),
+        locked,
+        wanted,
+        latest,
+        outdated,
+        error: refs.err().map(str::to_string),
+    }
+}
+
+fn wanted(
+    refs: &RemoteRefs,
+    git_ref: Option<&GitRef>,
+    version_req: Option<&VersionReq>,
+) -> Option<Revision> {
+    match (git_ref, version_req) {
+        (Some(GitRef::Tag(tag)), _) => refs.tag(tag).map(|rev| Revision {
+            version: version_from_tag(tag).map(|v| v.to_string()),
+            rev: rev.to_string(),
+        }),
+        (Some(GitRef::Branch(branch)), _) => refs.branch(branch).map(|rev| Revision {
+            version: None,
+            rev: rev.to_string(),
+        }),
+        (Some(GitRef::Rev(rev)), _) => Some(Revision {
+            version: None,
+            rev: rev.clone(),
+        }),
+        (None, Some(req)) => resolve_version(&refs.tags, req).map(|r| Revision {
+            version: Some(r.version.to_string()),
+            rev: r.rev,
+        }),
+        (None, None) => head(refs),
+    }
+}
+
+fn latest(refs: &RemoteRefs) -> Option<Revision> {
+    resolve_version(&refs.tags, &VersionReq::STAR)
+        .map(|r| Revision {
+            version: Some(r.version.to_string()),
+            rev: r.rev,
+        })
+        .or_else(|| head(refs))
+}
+
+fn head(refs: &RemoteRefs) -> Option<Revision> {
+    refs.head.as_ref().map(|rev| Revision {
+        version: None,
+        rev: rev.clone(),
+    })
+}
+
+fn print_table(rows: &[Row]) {
+    let cell = |r: &Option<Revision>| {
+        r.as_ref()
+            .map_or_else(|| "?".to_string(), |r| r.to_string())
+    };
+    let table: Vec<[String; 4]> = rows
+        .iter()
+        .map(|r| {
+            [
+                r.name.clone(),
+                cell(&r.locked),
+                cell(&r.wanted),
+                cell(&r.latest),
+            ]
+        })
+        .collect();
+    let header = ["Package", "Locked", "Wanted", "Latest"].map(String::from);
+
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &table {
+        for (w, c) in widths.iter_mut().zip(row) {
+            *w = (*w).max(c.chars().count());
+        }
+    }
+
+    for (row, outdated) in
+        std::iter::once((&header, false)).chain(table.iter().zip(rows.iter().map(|r| r.outdated)))
+    {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(c, w)| format!("{c:<w$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let marker = if outdated { " *" } else { "" };
+        println!("{}{marker}", line.trim_end());
+    }
+}
@@ -0,0 +1,113 @@
+use crate::commands::format_revision;
+use crate::commands::install::{self, InstallOptions};
+use crate::config::copm_json::{CopmJson, UNVERSIONED};
+use crate::config::lock::{CopmLock, LockedPackage};
+use crate::error::CopmError;
+use crate::installer::copilot;
+use crate::installer::transaction::Transaction;
+use crate::paths;
+
+/// Re-resolve dependencies from copm.json (ignoring copm.lock) and reinstall them.
+/// With no `packages`, every dependency is updated. Either every selected package is
+/// updated or, when one fails, nothing is changed.
+pub async fn run(packages: &[String]) -> Result<(), CopmError> {
+    let config = CopmJson::load(&paths::copm_json_path())?;
+    let lock = CopmLock::load(&paths::copm_lock_path())?;
+
+    for name in packages {
+        if !config.dependencies.contains_key(name) {
+            return Err(CopmError::NotInstalled(name.clone()));
+        }
+    }
+
+    let selected: Vec<_> = config
+        .dependencies
+        .iter()
+        .filter(|(name, _)| packages.is_empty() || packages.contains(name))
+        .collect();
+
+    if selected.is_empty() {
+        println!("No dependencies in copm.json.");
+        return Ok(());
+    }
+
+    // Every package is updated in one transaction: if any of them fails, the files
+    // and copm.json/copm.lock are restored to how they were before
+    let mut tx = Transaction::new();
+    let roots = paths::managed_roots();
+    let mut changes = Vec::new();
+    let mut failed = 0;
+    for (name, dep) in selected {
+        println!();
+        let old = lock.packages.iter().find(|p| &p.name == name);
+        let result = match install::dependency_spec(dep) {
+            Ok(spec) => install::install_spec(&spec, None, InstallOptions::default(), &mut tx).await,
+            Err(e) => Err(e),
+        };
+        let new = match result {
+            Ok(new) => new,
+            Err(e) => {
+                eprintln!("Failed to update {name}: {e}");
+                failed += 1;
+                continue;
+            }
+        };
+
+        // Remove files that no longer exist upstream
+        if let Some(old) = old {
+            let stale = stale_files(old, &new);
+            let (managed, skipped) = copilot::partition_managed(&stale, &roots);
+            for path in skipped {
+                eprintln!("Skipping {}: outside the directories copm installs into", path.display());
+            }
+            for path in managed {
+                println!("  ✗ {}", path.display());
+                tx.remove(&path)?;
+            }
+        }
+
+        changes.push(summary(name, old.map(describe).as_deref(), &describe(&new)));
+    }
+
+    println!();
+    if failed > 0 {
+        tx.rollback();
+        println!("Rolled back all changes.");
+        return Err(CopmError::InstallFailed(failed));
+    }
+    tx.commit();
+    for line in &changes {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// Files the old install recorded that the new one no longer has.
+pub fn stale_files(old: &LockedPackage, new: &LockedPackage) -> Vec<String> {
+    old.installed_files
+        .iter()
+        .filter(|f| !new.installed_files.contains(f))
+        .cloned()
+        .collect()
+}
+
+/// One line of the update report: `name: old → new`.
+pub fn summary(name: &str, old: Option<&str>, new: &str) -> String {
+    match old {
+        Some(old) if old == new => format!("{name}: {new} (up to date)"),
+        Some(old) => format!("{name}: {old} → {new}"),
+        None => format!("{name}: (new) → {new}"),
+    }
+}
+
+/// The installed revision as shown in the update report.
+pub fn describe(pkg: &LockedPackage) -> String {
+    let version = (pkg.version != UNVERSIONED).then_some(pkg.version.as_str());
+    // Local directories and archives have no commit; show their content hash instead
+    let hash = pkg
+        .integrity
+        .as_deref()
+        .filter(|_| !pkg.kind().has_commits())
+        .and_then(|i| i.rsplit('-').next());
+    format_revision(version, pkg.source.rev.as_deref().or(hash))
+}
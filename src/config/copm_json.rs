@@ -1,9 +1,12 @@
 use std::collections::BTreeMap;
 use std::path::Path;
 
+use semver::VersionReq;
 use serde::{Deserialize, Serialize};
 
 use crate::error::CopmError;
+use crate::fetcher::git::GitRef;
+use crate::fetcher::version::parse_version_req;
 
 fn default_tools() -> Vec<String> {
     vec!["copilot".to_string()]
@@ -21,9 +24,33 @@ pub struct CopmJson {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Dependency {
     pub source: String,
+    /// Semver range resolved against git tags (e.g. "^1.2"), or "0.0.0" to track
+    /// the default branch / pinned ref
     pub version: String,
+    /// Pinned tag, branch or commit, written as `"tag"`, `"branch"` or `"rev"`.
+    #[serde(flatten, default, skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<GitRef>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sub_path: Option<String>,
+    /// `sha256-…` checksum an archive URL download must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<String>,
+}
+
+/// Placeholder `version` for dependencies that are not resolved from tags.
+pub const UNVERSIONED: &str = "0.0.0";
+
+impl Dependency {
+    /// The semver range to resolve this dependency against, if any.
+    ///
+    /// Returns `None` when the dependency pins a tag/branch/commit or uses the
+    /// `"0.0.0"` placeholder, in which case `version` is informational only.
+    pub fn version_req(&self) -> Result<Option<VersionReq>, CopmError> {
+        if self.git_ref.is_some() || self.version == UNVERSIONED {
+            return Ok(None);
+        }
+        parse_version_req(&self.version).map(Some)
+    }
 }
 
 impl Default for CopmJson {
@@ -51,17 +78,31 @@ impl CopmJson {
 
     pub fn save(&self, path: &Path) -> Result<(), CopmError> {
         let content = serde_json::to_string_pretty(self)?;
-        std::fs::write(path, content + "\n")?;
-        Ok(())
+        super::write_atomic(path, (content + "\n").as_bytes())
     }
 
-    pub fn add_dependency(&mut self, name: &str, source: &str, version: &str, sub_path: Option<String>) {
+    pub fn add_dependency(
+        &mut self,
+        name: &str,
+        source: &str,
+        version: &str,
+        git_ref: Option<GitRef>,
+        sub_path: Option<String>,
+    ) {
+        // Keep a pinned checksum as long as the dependency still points at the same source
+        let integrity = self
+            .dependencies
+            .get(name)
+            .filter(|d| d.source == source)
+            .and_then(|d| d.integrity.clone());
         self.dependencies.insert(
             name.to_string(),
             Dependency {
                 source: source.to_string(),
                 version: version.to_string(),
+                git_ref,
                 sub_path,
+                integrity,
             },
         );
     }
@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::lock::{CopmLock, LockedPackage};
+use crate::error::CopmError;
+
+/// Scope of global installs in [`InstalledFiles`].
+pub const GLOBAL_SCOPE: &str = "global";
+
+/// Packages installed where no copm.lock keeps track of them: globally, or into a
+/// project that has neither copm.json nor copm.lock. Stored in ~/.copm/installed.json
+/// so that the conflict check can tell copm's own files from hand-written ones.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InstalledFiles {
+    /// copm.lock-style entries per scope: [`GLOBAL_SCOPE`] or a project's canonical path
+    #[serde(default)]
+    pub scopes: BTreeMap<String, CopmLock>,
+}
+
+impl InstalledFiles {
+    pub fn load(path: &Path) -> Result<Self, CopmError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CopmError> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_string_pretty(self)?;
+        super::write_atomic(path, (content + "\n").as_bytes())
+    }
+
+    /// The packages recorded in `scope`.
+    pub fn scope(&self, scope: &str) -> CopmLock {
+        self.scopes.get(scope).cloned().unwrap_or_default()
+    }
+
+    pub fn upsert_package(&mut self, scope: &str, pkg: LockedPackage) {
+        self.scopes.entry(scope.to_string()).or_default().upsert_package(pkg);
+    }
+
+    /// Forget `name` in `scope`, dropping the scope once it's empty.
+    pub fn remove_package(&mut self, scope: &str, name: &str) -> bool {
+        let Some(lock) = self.scopes.get_mut(scope) else {
+            return false;
+        };
+        let removed = lock.remove_package(name);
+        if lock.packages.is_empty() {
+            self.scopes.remove(scope);
+        }
+        removed
+    }
+}
+
+/// Where an install records which files it owns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OwnerRecord {
+    /// The project's copm.lock
+    Lock(PathBuf),
+    /// One scope of ~/.copm/installed.json
+    Installed { path: PathBuf, scope: String },
+}
+
+impl OwnerRecord {
+    /// The record for an install into `project_dir` (or a global one): its copm.lock when
+    /// it has copm.json or copm.lock, otherwise its scope in `installed_path`.
+    pub fn locate(global: bool, project_dir: &Path, installed_path: &Path) -> Result<Self, CopmError> {
+        let lock_path = project_dir.join("copm.lock");
+        let scope = if global {
+            GLOBAL_SCOPE.to_string()
+        } else if project_dir.join("copm.json").exists() || lock_path.exists() {
+            return Ok(Self::Lock(lock_path));
+        } else {
+            project_dir.canonicalize()?.to_string_lossy().to_string()
+        };
+        Ok(Self::Installed {
+            path: installed_path.to_path_buf(),
+            scope,
+        })
+    }
+
+    /// Which package installed each recorded path.
+    pub fn owners(&self) -> Result<CopmLock, CopmError> {
+        match self {
+            Self::Lock(path) => CopmLock::load(path),
+            Self::Installed { path, scope } => Ok(InstalledFiles::load(path)?.scope(scope)),
+        }
+    }
+}
@@ -2,7 +2,10 @@ use std::path::Path;
 
 use serde::{Deserialize, Serialize};
 
+use crate::config::copm_json::{CopmJson, Dependency};
 use crate::error::CopmError;
+use crate::fetcher::git::{parse_package_spec, GitRef, PackageSpec};
+use crate::fetcher::source::SourceKind;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CopmLock {
@@ -29,6 +32,16 @@ pub struct LockedSource {
     #[serde(rename = "type")]
     pub source_type: String,
     pub repo: String,
+    /// GitHub Enterprise Server host the package came from (github.com when absent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Clone URL of a `git+<url>` source, or the download URL of an archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Ref requested in copm.json (tag/branch/commit), if any
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<GitRef>,
+    /// Commit SHA that `git_ref` (or the default branch) resolved to
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rev: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -44,7 +57,113 @@ impl Default for CopmLock {
     }
 }
 
+impl LockedSource {
+    /// The package this entry was installed from. Recorded sources name their host
+    /// explicitly, so the current user's `default_host` is never applied.
+    pub fn spec(&self) -> Result<PackageSpec, CopmError> {
+        let mut spec = parse_package_spec(&self.repo)?;
+        if spec.host.is_none() {
+            spec.host = self.host.clone();
+        }
+        Ok(spec)
+    }
+}
+
+impl LockedPackage {
+    /// Differences between this entry and the copm.json dependency it was installed from
+    /// (source, ref, sub_path, or a locked version outside the dependency's range).
+    pub fn differences_from(&self, dep: &Dependency) -> Vec<String> {
+        let mut diffs = Vec::new();
+        if self.source.repo != dep.source {
+            diffs.push(format!(
+                "source differs (copm.json: {}, copm.lock: {})",
+                dep.source, self.source.repo
+            ));
+        }
+        if self.source.git_ref != dep.git_ref {
+            let show = |r: &Option<GitRef>| r.as_ref().map_or_else(|| "none".to_string(), |r| r.to_string());
+            diffs.push(format!(
+                "ref differs (copm.json: {}, copm.lock: {})",
+                show(&dep.git_ref),
+                show(&self.source.git_ref)
+            ));
+        }
+        if self.source.sub_path != dep.sub_path {
+            let show = |s: &Option<String>| s.clone().unwrap_or_else(|| "none".to_string());
+            diffs.push(format!(
+                "sub_path differs (copm.json: {}, copm.lock: {})",
+                show(&dep.sub_path),
+                show(&self.source.sub_path)
+            ));
+        }
+        match dep.version_req() {
+            Ok(Some(req)) => {
+                if !semver::Version::parse(&self.version).is_ok_and(|v| req.matches(&v)) {
+                    diffs.push(format!("locked version {} does not satisfy {req}", self.version));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => diffs.push(e.to_string()),
+        }
+        diffs
+    }
+
+    /// Whether this entry pins what was installed: a commit, or for sources without
+    /// commits (local directories, archive URLs) the integrity hash.
+    pub fn is_pinned(&self) -> bool {
+        self.source.rev.is_some() || (!self.kind().has_commits() && self.integrity.is_some())
+    }
+
+    /// The kind of source this entry was installed from (GitHub for unknown types).
+    pub fn kind(&self) -> SourceKind {
+        SourceKind::from_name(&self.source.source_type).unwrap_or_default()
+    }
+
+    /// Whether this entry was installed from a local directory.
+    pub fn is_local(&self) -> bool {
+        self.kind() == SourceKind::Path
+    }
+
+    /// Whether this entry is pinned and still describes `dep`.
+    pub fn matches_dependency(&self, dep: &Dependency) -> bool {
+        self.is_pinned() && self.differences_from(dep).is_empty()
+    }
+}
+
 impl CopmLock {
+    /// The package whose `installed_files` include `path` (or a directory containing it).
+    pub fn owner_of(&self, path: &Path) -> Option<&str> {
+        self.packages
+            .iter()
+            .find(|p| p.installed_files.iter().any(|f| path.starts_with(f)))
+            .map(|p| p.name.as_str())
+    }
+
+    /// Every way in which this lock disagrees with `config`, one line per problem.
+    /// An empty result means `copm install --frozen` can reproduce the lock exactly.
+    pub fn drift_from(&self, config: &CopmJson) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (name, dep) in &config.dependencies {
+            match self.packages.iter().find(|p| &p.name == name) {
+                None => problems.push(format!("{name}: missing from copm.lock")),
+                Some(pkg) => {
+                    if !pkg.is_pinned() {
+                        problems.push(format!("{name}: no commit recorded in copm.lock"));
+                    }
+                    for diff in pkg.differences_from(dep) {
+                        problems.push(format!("{name}: {diff}"));
+                    }
+                }
+            }
+        }
+        for pkg in &self.packages {
+            if !config.dependencies.contains_key(&pkg.name) {
+                problems.push(format!("{}: in copm.lock but not in copm.json", pkg.name));
+            }
+        }
+        problems
+    }
+
     pub fn load(path: &Path) -> Result<Self, CopmError> {
         if !path.exists() {
             return Ok(Self::default());
@@ -56,8 +175,7 @@ impl CopmLock {
 
     pub fn save(&self, path: &Path) -> Result<(), CopmError> {
         let content = serde_json::to_string_pretty(self)?;
-        std::fs::write(path, content + "\n")?;
-        Ok(())
+        super::write_atomic(path, (content + "\n").as_bytes())
     }
 
     pub fn upsert_package(&mut self, pkg: LockedPackage) {
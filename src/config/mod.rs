@@ -1,2 +1,37 @@
 pub mod copm_json;
+pub mod installed;
 pub mod lock;
+pub mod process_lock;
+pub mod user_config;
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::CopmError;
+
+/// Replace `path` with `content` by writing a temporary file beside it and renaming it
+/// over the original, so readers see either the old or the new file, never a partial one.
+///
+/// The file keeps the permissions of the one it replaces; a new file gets the ones
+/// `std::fs::write` would give it (0644 under the usual umask), not the temporary
+/// file's owner-only 0600.
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<(), CopmError> {
+    let dir = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut builder = tempfile::Builder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(std::fs::Permissions::from_mode(0o666));
+    }
+    let mut tmp = builder.tempfile_in(dir)?;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        tmp.as_file().set_permissions(metadata.permissions())?;
+    }
+    tmp.write_all(content)?;
+    tmp.as_file().sync_all()?;
+    tmp.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
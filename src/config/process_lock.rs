@@ -0,0 +1,91 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::CopmError;
+use crate::paths;
+
+/// An advisory lock held by a command that changes copm.json, copm.lock, installed
+/// files or the download cache, so that two copm processes (e.g. a git hook and a
+/// terminal) take turns. Released when dropped or when the process exits.
+#[derive(Debug)]
+pub struct ProcessLock {
+    file: File,
+    shared: bool,
+}
+
+impl ProcessLock {
+    /// Lock `path` exclusively, creating it if needed. Waits, with a message, while
+    /// another process holds it.
+    pub fn acquire(path: &Path) -> Result<Self, CopmError> {
+        Self::open(path, false)
+    }
+
+    /// Lock `path` shared with other readers; waits while a process holds it exclusively.
+    pub fn acquire_shared(path: &Path) -> Result<Self, CopmError> {
+        Self::open(path, true)
+    }
+
+    /// The current project's lock, `.copm.lock.pid` in the project itself so that every
+    /// process working on the checkout sees it, whatever its home directory.
+    pub fn project() -> Result<Self, CopmError> {
+        Self::acquire(&paths::process_lock_path())
+    }
+
+    /// The lock on ~/.copm, taken exclusively by global installs and by commands that
+    /// change the download cache.
+    pub fn home() -> Result<Self, CopmError> {
+        Self::acquire(&paths::global_process_lock_path()?)
+    }
+
+    /// The lock on ~/.copm, shared by project installs reading from the download cache.
+    pub fn home_shared() -> Result<Self, CopmError> {
+        Self::acquire_shared(&paths::global_process_lock_path()?)
+    }
+
+    fn open(path: &Path, shared: bool) -> Result<Self, CopmError> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        let attempt = if shared { file.try_lock_shared() } else { file.try_lock() };
+        match attempt {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = std::fs::read_to_string(path).unwrap_or_default();
+                match holder.trim() {
+                    "" => println!("Waiting for another copm process to finish ({})...", path.display()),
+                    pid => println!(
+                        "Waiting for another copm process (pid {pid}) to finish ({})...",
+                        path.display()
+                    ),
+                }
+                if shared {
+                    file.lock_shared()?;
+                } else {
+                    file.lock()?;
+                }
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        // Say who holds it, for the message above; shared holders leave it alone
+        if !shared {
+            file.set_len(0)?;
+            writeln!(&file, "{}", std::process::id())?;
+        }
+        Ok(Self { file, shared })
+    }
+}
+
+impl Drop for ProcessLock {
+    fn drop(&mut self) {
+        // Clear the pid so that nobody waits on it after we've gone
+        if !self.shared {
+            let _ = self.file.set_len(0);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::CopmError;
+use crate::paths;
+
+/// Per-user settings in ~/.copm/config.json. Never committed to a project.
+///
+/// ```json
+/// {
+///   "default_host": "ghe.example.com",
+///   "hosts": { "github.com": { "token": "ghp_..." } },
+///   "limits": { "max_total_size": 536870912, "max_files": 20000 }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserConfig {
+    /// Host that unqualified `owner/repo` specifiers are fetched from (default github.com)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_host: Option<String>,
+    #[serde(default)]
+    pub hosts: BTreeMap<String, HostConfig>,
+    #[serde(default)]
+    pub limits: Limits,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HostConfig {
+    /// Access token sent to this host's API and git endpoints
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// REST API base, when it isn't `https://{host}/api/v3`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+}
+
+/// How much a single package may unpack or install.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Total bytes of regular files
+    pub max_total_size: u64,
+    /// Number of regular files
+    pub max_files: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_total_size: 512 * 1024 * 1024,
+            max_files: 20_000,
+        }
+    }
+}
+
+impl UserConfig {
+    pub fn load(path: &Path) -> Result<Self, CopmError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)?;
+        let config: Self = serde_json::from_str(&content)?;
+        Ok(config)
+    }
+
+    /// The current user's config; a missing or unreadable file counts as empty.
+    pub fn load_default() -> Self {
+        paths::user_config_path()
+            .and_then(|p| Self::load(&p))
+            .unwrap_or_default()
+    }
+
+    pub fn host(&self, host: &str) -> Option<&HostConfig> {
+        self.hosts.get(host)
+    }
+}
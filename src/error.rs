@@ -5,6 +5,12 @@ pub enum CopmError {
     #[error("Invalid package specifier: {0}")]
     InvalidPackageSpec(String),
 
+    #[error("Invalid version requirement: {0}")]
+    InvalidVersionReq(String),
+
+    #[error("No tag of {pkg} matches version {req}")]
+    NoMatchingVersion { pkg: String, req: String },
+
     #[error("Package not found: {0}")]
     PackageNotFound(String),
 
@@ -29,6 +35,42 @@ pub enum CopmError {
     #[error("Unsupported target type: {0}")]
     UnsupportedTargetType(String),
 
+    #[error("Integrity check failed for {pkg}:\n  expected {expected}\n  got      {actual}\nIf this change is expected, rerun with --update-integrity")]
+    IntegrityMismatch { pkg: String, expected: String, actual: String },
+
+    #[error("Cannot check {pkg} against copm.lock: it records {expected}, but the download produced {actual}\nIf this change is expected, rerun without --frozen and with --update-integrity")]
+    IntegrityUnverifiable { pkg: String, expected: String, actual: String },
+
+    #[error("copm.lock is out of date with copm.json:\n{0}\nRun `copm install` without --frozen to update it")]
+    LockOutOfDate(String),
+
+    #[error("{0} is not in the download cache (offline mode)")]
+    NotCached(String),
+
+    #[error("Offline mode: these packages are not available in the download cache:\n{0}\nRun `copm install` once with network access (or `copm cache add <package>`) to cache them")]
+    OfflineUnavailable(String),
+
+    #[error("{0} cached download(s) did not match their recorded hash and were removed")]
+    CacheCorrupt(usize),
+
+    #[error("Invalid age '{0}' (expected e.g. 30d, 12h, 45m)")]
+    InvalidAge(String),
+
+    #[error("These files already exist and were not installed by this package:\n{0}\nRerun with --force to replace them (the originals are moved to .copm-backup/, or ~/.copm/backups/ with -g) or --skip-existing to leave them alone")]
+    InstallConflict(String),
+
+    #[error("Unsafe package content: {0}")]
+    UnsafeArchive(String),
+
+    #[error("{0} package(s) failed to install")]
+    InstallFailed(usize),
+
+    #[error("{0} package(s) are outdated")]
+    Outdated(usize),
+
+    #[error("Could not check {0} package(s) against upstream")]
+    OutdatedUnknown(usize),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -0,0 +1,83 @@
+use base64::Engine;
+
+use crate::config::user_config::UserConfig;
+
+/// Find an access token for `host`, in order of precedence:
+///
+/// 1. `GITHUB_TOKEN` / `GH_TOKEN` (github.com only)
+/// 2. `hosts.<host>.token` in ~/.copm/config.json
+/// 3. A `machine <host>` (or `api.<host>`) entry in ~/.netrc
+pub fn token_for_host(host: &str) -> Option<String> {
+    let user_config = UserConfig::load_default();
+    let netrc = dirs::home_dir().and_then(|h| std::fs::read_to_string(h.join(".netrc")).ok());
+    resolve_token(
+        host,
+        |k| std::env::var(k).ok(),
+        &user_config,
+        netrc.as_deref(),
+    )
+}
+
+/// Token lookup with the environment, user config and netrc contents passed in.
+pub fn resolve_token(
+    host: &str,
+    env: impl Fn(&str) -> Option<String>,
+    user_config: &UserConfig,
+    netrc: Option<&str>,
+) -> Option<String> {
+    let non_empty = |t: String| (!t.trim().is_empty()).then(|| t.trim().to_string());
+
+    if host == "github.com" {
+        if let Some(token) = ["GITHUB_TOKEN", "GH_TOKEN"]
+            .iter()
+            .find_map(|k| env(k).and_then(non_empty))
+        {
+            return Some(token);
+        }
+    }
+    if let Some(token) = user_config
+        .host(host)
+        .and_then(|h| h.token.clone())
+        .and_then(non_empty)
+    {
+        return Some(token);
+    }
+    let netrc = netrc?;
+    netrc_password(netrc, host).or_else(|| netrc_password(netrc, &format!("api.{host}")))
+}
+
+/// The password of the `machine` entry for `host` in a .netrc file.
+pub fn netrc_password(content: &str, host: &str) -> Option<String> {
+    let mut tokens = content.split_whitespace();
+    let mut in_machine = false;
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => in_machine = tokens.next() == Some(host),
+            "default" => in_machine = false,
+            "password" if in_machine => return tokens.next().map(str::to_string),
+            "login" | "password" | "account" => {
+                tokens.next();
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Environment variables that make git send `token` (as the password for `username`)
+/// to `https://{host}/` without putting it on the command line or in any config file.
+pub fn git_auth_env(host: &str, username: &str, token: &str) -> Vec<(String, String)> {
+    let credentials =
+        base64::engine::general_purpose::STANDARD.encode(format!("{username}:{token}"));
+    vec![
+        ("GIT_CONFIG_COUNT".to_string(), "1".to_string()),
+        (
+            "GIT_CONFIG_KEY_0".to_string(),
+            format!("http.https://{host}/.extraheader"),
+        ),
+        (
+            "GIT_CONFIG_VALUE_0".to_string(),
+            format!("Authorization: Basic {credentials}"),
+        ),
+    ]
+}
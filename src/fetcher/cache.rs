@@ -0,0 +1,275 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::error::CopmError;
+use crate::paths;
+
+const META_FILE: &str = "entry.json";
+const TREE_DIR: &str = "tree";
+
+/// Metadata stored next to each cached tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheMeta {
+    /// Package source, e.g. "owner/repo"
+    pub source: String,
+    /// Commit the tree was extracted from
+    pub rev: String,
+    /// Integrity of the original download (as recorded in copm.lock)
+    pub integrity: String,
+    /// `hash_dir()` of the extracted tree, used to detect modified cache entries
+    pub tree_hash: String,
+    /// Total size of the extracted files in bytes
+    pub size: u64,
+    /// Unix timestamps (seconds)
+    pub created_at: u64,
+    pub last_used: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub dir: PathBuf,
+    pub meta: CacheMeta,
+}
+
+impl CacheEntry {
+    /// The extracted package contents.
+    pub fn tree_dir(&self) -> PathBuf {
+        self.dir.join(TREE_DIR)
+    }
+
+    /// Whether the tree still hashes to the value recorded when it was cached.
+    pub fn verify(&self) -> bool {
+        hash_dir(&self.tree_dir()).is_ok_and(|h| h == self.meta.tree_hash)
+    }
+
+    pub fn remove(&self) -> Result<(), CopmError> {
+        std::fs::remove_dir_all(&self.dir)?;
+        Ok(())
+    }
+}
+
+/// Content-addressed cache of extracted package trees, keyed by source + commit.
+///
+/// ```text
+/// ~/.copm/cache/<sha256(source@rev)>/
+/// ├── entry.json
+/// └── tree/
+/// ```
+pub struct Cache {
+    root: PathBuf,
+}
+
+impl Cache {
+    /// The user's cache in ~/.copm/cache/
+    pub fn open() -> Result<Self, CopmError> {
+        Ok(Self::at(paths::cache_dir()?))
+    }
+
+    pub fn at(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn entry_dir(&self, source: &str, rev: &str) -> PathBuf {
+        let key = hex::encode(Sha256::digest(format!("{source}@{rev}")));
+        self.root.join(key)
+    }
+
+    /// Look up a cached tree. Entries whose contents no longer match their recorded
+    /// hash are discarded and reported as a miss.
+    ///
+    /// The tree is only checked against the `tree_hash` in its own entry.json, not
+    /// against copm.lock: the integrity a hit reports is the one recorded when it was
+    /// downloaded.
+    pub fn get(&self, source: &str, rev: &str) -> Option<CacheEntry> {
+        let dir = self.entry_dir(source, rev);
+        let mut entry = read_entry(&dir)?;
+        if entry.meta.source != source || entry.meta.rev != rev {
+            return None;
+        }
+        if !entry.verify() {
+            let _ = entry.remove();
+            return None;
+        }
+
+        entry.meta.last_used = now();
+        let _ = write_meta(&dir, &entry.meta);
+        Some(entry)
+    }
+
+    /// Whether there is an entry for `source` at `rev`, without checking its contents.
+    pub fn contains(&self, source: &str, rev: &str) -> bool {
+        read_entry(&self.entry_dir(source, rev)).is_some_and(|e| e.meta.source == source && e.meta.rev == rev)
+    }
+
+    /// Whether [`Cache::get_with_subpath`] has an entry to check, without hashing it:
+    /// the tree is verified once, when it's used.
+    pub fn contains_with_subpath(&self, source: &str, sub_path: Option<&str>, rev: &str) -> bool {
+        self.contains(source, rev) || sub_path.is_some_and(|sp| self.contains(&partial_source(source, sp), rev))
+    }
+
+    /// The full SHA of the one cached commit of `source` (or of a subpath of it) that
+    /// starts with the abbreviated `rev`.
+    pub fn find_rev(&self, source: &str, rev: &str) -> Option<String> {
+        let subpath_prefix = format!("{source}:");
+        let mut revs: Vec<String> = self
+            .entries()
+            .ok()?
+            .into_iter()
+            .filter(|e| e.meta.source == source || e.meta.source.starts_with(&subpath_prefix))
+            .filter(|e| e.meta.rev.starts_with(rev))
+            .map(|e| e.meta.rev)
+            .collect();
+        revs.sort();
+        revs.dedup();
+        match <[String; 1]>::try_from(revs) {
+            Ok([full]) => Some(full),
+            Err(_) => None,
+        }
+    }
+
+    /// Look up `source` at `rev`, falling back to a tree cached for just `sub_path`
+    /// (see [`partial_source`]).
+    pub fn get_with_subpath(&self, source: &str, sub_path: Option<&str>, rev: &str) -> Option<CacheEntry> {
+        self.get(source, rev)
+            .or_else(|| self.get(&partial_source(source, sub_path?), rev))
+    }
+
+    /// Every readable entry, sorted by source then rev.
+    pub fn entries(&self) -> Result<Vec<CacheEntry>, CopmError> {
+        if !self.root.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut entries: Vec<CacheEntry> = std::fs::read_dir(&self.root)?
+            .flatten()
+            .filter_map(|e| read_entry(&e.path()))
+            .collect();
+        entries.sort_by(|a, b| (&a.meta.source, &a.meta.rev).cmp(&(&b.meta.source, &b.meta.rev)));
+        Ok(entries)
+    }
+
+    /// Remove every entry. Only directories holding an `entry.json` are deleted, so a
+    /// cache root pointed somewhere else by mistake (`COPM_CACHE_DIR=$HOME`) keeps
+    /// everything that isn't a cache entry. Returns the entries removed.
+    pub fn clear(&self) -> Result<Vec<CacheEntry>, CopmError> {
+        let entries = self.entries()?;
+        for entry in &entries {
+            entry.remove()?;
+        }
+        Ok(entries)
+    }
+
+    /// Copy `tree` (minus any `.git` directory) into the cache.
+    pub fn put(
+        &self,
+        source: &str,
+        rev: &str,
+        integrity: &str,
+        tree: &Path,
+    ) -> Result<CacheEntry, CopmError> {
+        let dir = self.entry_dir(source, rev);
+        // Another process may be reading an existing entry, so it is never replaced
+        if let Some(entry) = read_entry(&dir) {
+            return Ok(entry);
+        }
+        std::fs::create_dir_all(&self.root)?;
+
+        // Build the entry in a staging dir so a partial copy is never visible
+        let staging = tempfile::tempdir_in(&self.root)?;
+        let staged_tree = staging.path().join(TREE_DIR);
+        let size = copy_tree(tree, &staged_tree)?;
+        let timestamp = now();
+        let meta = CacheMeta {
+            source: source.to_string(),
+            rev: rev.to_string(),
+            integrity: integrity.to_string(),
+            tree_hash: hash_dir(&staged_tree)?,
+            size,
+            created_at: timestamp,
+            last_used: timestamp,
+        };
+        write_meta(staging.path(), &meta)?;
+
+        if let Err(e) = std::fs::rename(staging.path(), &dir) {
+            // Another download of the same commit finished first; its entry is as good
+            return read_entry(&dir).ok_or(e.into());
+        }
+        Ok(CacheEntry { dir, meta })
+    }
+}
+
+fn read_entry(dir: &Path) -> Option<CacheEntry> {
+    let content = std::fs::read_to_string(dir.join(META_FILE)).ok()?;
+    let meta: CacheMeta = serde_json::from_str(&content).ok()?;
+    Some(CacheEntry {
+        dir: dir.to_path_buf(),
+        meta,
+    })
+}
+
+fn write_meta(dir: &Path, meta: &CacheMeta) -> Result<(), CopmError> {
+    let content = serde_json::to_string_pretty(meta)?;
+    std::fs::write(dir.join(META_FILE), content + "\n")?;
+    Ok(())
+}
+
+/// Copy a directory tree, skipping `.git`. Returns the number of bytes copied.
+fn copy_tree(src: &Path, dst: &Path) -> Result<u64, CopmError> {
+    std::fs::create_dir_all(dst)?;
+    let mut size = 0;
+    let walker = WalkDir::new(src)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git");
+    for entry in walker {
+        let entry = entry.map_err(|e| CopmError::Io(std::io::Error::other(e.to_string())))?;
+        let relative = entry.path().strip_prefix(src).unwrap();
+        let dest_path = dst.join(relative);
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&dest_path)?;
+        } else if entry.file_type().is_file() {
+            size += std::fs::copy(entry.path(), &dest_path)?;
+        }
+    }
+    Ok(size)
+}
+
+/// Cache key for a tree that holds only `sub_path` of `source`.
+pub fn partial_source(source: &str, sub_path: &str) -> String {
+    format!("{source}:{sub_path}")
+}
+
+/// Hash the contents of a directory: every regular file's relative path and bytes,
+/// in sorted order, ignoring `.git`. Returns `sha256-<hex>`.
+pub fn hash_dir(dir: &Path) -> Result<String, CopmError> {
+    let mut hasher = Sha256::new();
+    let walker = WalkDir::new(dir)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git");
+    for entry in walker {
+        let entry = entry.map_err(|e| CopmError::Io(std::io::Error::other(e.to_string())))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(dir).unwrap();
+        let content = std::fs::read(entry.path())?;
+        hasher.update(relative.to_string_lossy().replace('\\', "/").as_bytes());
+        hasher.update([0]);
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(&content);
+    }
+    Ok(format!("sha256-{}", hex::encode(hasher.finalize())))
+}
+
+/// Current Unix time in seconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
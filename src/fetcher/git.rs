@@ -1,60 +1,583 @@
+use std::fmt;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
+use semver::VersionReq;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
+use crate::config::user_config::{Limits, UserConfig};
 use crate::error::CopmError;
+use crate::fetcher::auth;
+use crate::fetcher::cache::{hash_dir, partial_source, Cache};
+use crate::fetcher::limits::{check_entry_path, Budget};
+use crate::fetcher::source::SourceKind;
+use crate::fetcher::version::parse_version_req;
+
+/// Host that `owner/repo` specs are fetched from unless another one is given
+pub const GITHUB_HOST: &str = "github.com";
 
 pub struct FetchResult {
     pub extracted_dir: PathBuf,
     pub integrity: String,
+    /// Commit SHA the fetched content was taken from, when it could be determined
+    pub rev: Option<String>,
+}
+
+/// A git reference pinned in a package specifier.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GitRef {
+    /// `owner/repo@v1.2.0`
+    Tag(String),
+    /// `owner/repo#main`
+    Branch(String),
+    /// `owner/repo@<commit sha>`
+    Rev(String),
+}
+
+impl GitRef {
+    /// The raw ref name (tag, branch or commit) as understood by git and the GitHub API.
+    pub fn as_str(&self) -> &str {
+        match self {
+            GitRef::Tag(s) | GitRef::Branch(s) | GitRef::Rev(s) => s,
+        }
+    }
+}
+
+impl fmt::Display for GitRef {
+    /// Formats the ref the way it is written in a package specifier.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitRef::Tag(s) | GitRef::Rev(s) => write!(f, "@{s}"),
+            GitRef::Branch(s) => write!(f, "#{s}"),
+        }
+    }
+}
+
+/// A parsed package specifier:
+/// `[kind:][host/]owner/repo[@tag|@sha|@range|#branch][:subpath]`,
+/// `git+<url>[#ref][:subpath]`, `[path:]<dir>[:subpath]` or `<archive url>[:subpath]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageSpec {
+    pub kind: SourceKind,
+    /// Self-hosted instance (`ghe.example.com/owner/repo`) or the host of a git URL;
+    /// `None` means the forge's public host
+    pub host: Option<String>,
+    /// Owner, workspace or (for GitLab) the full group path, e.g. "group/subgroup".
+    /// Empty for git URLs.
+    pub owner: String,
+    /// Repository name (for git URLs and local directories, the last path segment
+    /// without `.git`)
+    pub repo: String,
+    /// Clone URL of a `git+<url>` spec (without the `git+` prefix), or the download URL
+    /// of an archive
+    pub url: Option<String>,
+    /// Directory of a local source, as written (relative to the project root)
+    pub path: Option<String>,
+    pub git_ref: Option<GitRef>,
+    /// Semver range resolved against the repository's tags (mutually exclusive with `git_ref`)
+    pub version_req: Option<VersionReq>,
+    pub sub_path: Option<String>,
+    /// Checksum the download must match (`"integrity"` in copm.json); never part of
+    /// the specifier string
+    pub integrity: Option<String>,
+}
+
+impl PackageSpec {
+    /// "owner/repo", prefixed with the host and forge where needed, e.g.
+    /// "ghe.example.com/owner/repo" or "gitlab:group/subgroup/project"
+    /// (the value stored as `source` in copm.json)
+    pub fn source_label(&self) -> String {
+        if let Some(url) = &self.url {
+            return match self.kind {
+                SourceKind::Archive => url.clone(),
+                _ => format!("git+{url}"),
+            };
+        }
+        if let Some(path) = &self.path {
+            return if is_local_path(path) {
+                path.clone()
+            } else {
+                format!("path:{path}")
+            };
+        }
+        let path = match &self.host {
+            Some(host) => format!("{host}/{}/{}", self.owner, self.repo),
+            None => format!("{}/{}", self.owner, self.repo),
+        };
+        match self.kind {
+            SourceKind::GitHub => path,
+            kind => format!("{kind}:{path}"),
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        self.host
+            .as_deref()
+            .or(self.kind.default_host())
+            .unwrap_or_default()
+    }
+
+    /// REST API base for the host (e.g. `https://api.github.com`, `https://{host}/api/v3`
+    /// for GitHub Enterprise Server), unless overridden by `hosts.<host>.api_url` in
+    /// ~/.copm/config.json.
+    pub fn api_base(&self) -> String {
+        let host = self.host();
+        match UserConfig::load_default().host(host).and_then(|h| h.api_url.clone()) {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => self.kind.api_base(host),
+        }
+    }
+
+    /// Tarball download URL at `git_ref` (the default branch when `None`), if the
+    /// source has one
+    pub fn archive_url(&self, git_ref: Option<&GitRef>) -> Option<String> {
+        if self.url.is_some() || self.path.is_some() {
+            return None;
+        }
+        self.kind.archive_url(
+            &self.api_base(),
+            self.host(),
+            &self.owner,
+            &self.repo,
+            git_ref.map(GitRef::as_str),
+        )
+    }
+
+    pub fn clone_url(&self) -> String {
+        match &self.url {
+            Some(url) => url.clone(),
+            None => format!("https://{}/{}/{}.git", self.host(), self.owner, self.repo),
+        }
+    }
 }
 
-/// Parse "owner/repo" or "owner/repo:subpath" into (owner, repo, subpath).
-pub fn parse_package_spec(spec: &str) -> Result<(String, String, Option<String>), CopmError> {
+impl fmt::Display for PackageSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source_label())?;
+        match &self.git_ref {
+            // Git URLs may contain '@', so any ref follows '#'
+            Some(r) if self.url.is_some() => write!(f, "#{}", r.as_str())?,
+            Some(r) => write!(f, "{r}")?,
+            None => {}
+        }
+        if let Some(req) = &self.version_req {
+            write!(f, "@{req}")?;
+        }
+        if let Some(sp) = &self.sub_path {
+            write!(f, ":{sp}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a package specifier.
+///
+/// - `owner/repo`             → default branch
+/// - `owner/repo@v1.2.0`      → tag
+/// - `owner/repo@<sha>`       → commit (7–40 hex characters)
+/// - `owner/repo@^1.2`        → highest tag matching the semver range
+/// - `owner/repo#branch`      → branch
+///
+/// Any of the above may be prefixed with a host (`ghe.example.com/owner/repo`) and
+/// followed by `:subpath`. Other forges are selected with a prefix:
+///
+/// - `gitlab:group/subgroup/project` (gitlab.com, or `gitlab:host/group/project`)
+/// - `bitbucket:workspace/repo`
+/// - `gitea:host/owner/repo`
+/// - `git+https://host/path.git`, `git+ssh://git@host/path.git`, `git+file:///path/repo.git`
+///   (any clone URL; a ref is written `#ref`)
+/// - `./dir`, `../dir` or `path:dir` (a local directory; absolute paths need `path:`)
+/// - `https://example.com/skills-1.4.tar.gz` (a `.tar.gz`, `.tgz` or `.zip` download)
+pub fn parse_package_spec(spec: &str) -> Result<PackageSpec, CopmError> {
+    let invalid = || CopmError::InvalidPackageSpec(spec.to_string());
+
+    if spec.starts_with("https://") || spec.starts_with("http://") {
+        return parse_archive_url_spec(spec).ok_or_else(invalid);
+    }
+    if let Some(url) = spec.strip_prefix("git+") {
+        return parse_git_url_spec(url).ok_or_else(invalid);
+    }
+    if let Some(path) = spec.strip_prefix("path:") {
+        return parse_path_spec(path).ok_or_else(invalid);
+    }
+    if is_local_path(spec) {
+        return parse_path_spec(spec).ok_or_else(invalid);
+    }
+
+    // Forge prefix ("gitlab:", ...); anything else before a ':' is a GitHub repo
+    let (kind, rest) = match spec.split_once(':') {
+        Some((prefix, rest)) => match SourceKind::from_prefix(prefix) {
+            Some(kind) => (kind, rest),
+            None => (SourceKind::GitHub, spec),
+        },
+        None => (SourceKind::GitHub, spec),
+    };
+
     // Split on ':' first to separate sub_path
-    let (repo_part, sub_path) = match spec.split_once(':') {
+    let (repo_part, sub_path) = match rest.split_once(':') {
         Some((r, s)) => (r, Some(s.to_string())),
-        None => (spec, None),
+        None => (rest, None),
+    };
+
+    // Then split off the ref ("@tag", "@sha", "@range" or "#branch")
+    let mut version_req = None;
+    let (repo_part, git_ref) = if let Some((r, b)) = repo_part.split_once('#') {
+        (r, Some(GitRef::Branch(b.to_string())))
+    } else if let Some((r, t)) = repo_part.split_once('@') {
+        if t.starts_with(['^', '~', '=', '<', '>', '*']) {
+            version_req = Some(parse_version_req(t)?);
+            (r, None)
+        } else if is_commit_sha(t) {
+            (r, Some(GitRef::Rev(t.to_string())))
+        } else {
+            (r, Some(GitRef::Tag(t.to_string())))
+        }
+    } else {
+        (repo_part, None)
     };
 
-    let parts: Vec<&str> = repo_part.splitn(2, '/').collect();
-    if parts.len() != 2 || parts[0].is_empty() || parts[1].is_empty() {
-        return Err(CopmError::InvalidPackageSpec(spec.to_string()));
+    // A leading segment with a dot is a host: GitHub owners can't contain one
+    let parts: Vec<&str> = repo_part.split('/').collect();
+    let (host, parts) = match parts.as_slice() {
+        [host, rest @ ..] if host.contains('.') => (Some(host.to_string()), rest),
+        _ => (None, parts.as_slice()),
+    };
+    if parts.iter().any(|p| p.is_empty()) {
+        return Err(invalid());
+    }
+    let valid = match kind {
+        // Nested groups: everything before the project name is the group path
+        SourceKind::GitLab => parts.len() >= 2,
+        SourceKind::Bitbucket => parts.len() == 2 && host.is_none(),
+        SourceKind::Gitea => parts.len() == 2 && host.is_some(),
+        // GitHub (git URLs and paths are parsed separately)
+        _ => parts.len() == 2,
+    };
+    if !valid {
+        return Err(invalid());
     }
+    let (owner, repo) = parts.split_at(parts.len() - 1);
 
-    // Validate sub_path is not empty when provided
+    // Validate ref and sub_path are not empty when provided
+    if let Some(ref r) = git_ref {
+        if r.as_str().is_empty() || r.as_str().contains(['@', '#']) {
+            return Err(invalid());
+        }
+    }
     if let Some(ref sp) = sub_path {
         if sp.is_empty() {
-            return Err(CopmError::InvalidPackageSpec(spec.to_string()));
+            return Err(invalid());
+        }
+    }
+
+    Ok(PackageSpec {
+        kind,
+        host,
+        owner: owner.join("/"),
+        repo: repo[0].to_string(),
+        url: None,
+        path: None,
+        git_ref,
+        version_req,
+        sub_path,
+        integrity: None,
+    })
+}
+
+/// Parse the part of a `git+<url>[#ref][:subpath]` spec after `git+`.
+///
+/// A `:subpath` is only recognised after the URL's path has started, so ports
+/// (`ssh://host:2222/repo.git`) are left alone. `#ref` is a commit when it looks like
+/// one and a branch (or tag) otherwise.
+fn parse_git_url_spec(spec: &str) -> Option<PackageSpec> {
+    let (scheme, rest) = spec.split_once("://")?;
+    if !is_url_scheme(scheme) {
+        return None;
+    }
+
+    let path_start = rest.find('/')?;
+    let (url_rest, git_ref, sub_path) = match rest.split_once('#') {
+        Some((url_rest, fragment)) => {
+            let (r, sp) = match fragment.split_once(':') {
+                Some((r, sp)) => (r, Some(sp)),
+                None => (fragment, None),
+            };
+            let git_ref = if is_commit_sha(r) {
+                GitRef::Rev(r.to_string())
+            } else {
+                GitRef::Branch(r.to_string())
+            };
+            (url_rest, Some(git_ref), sp)
+        }
+        None => match rest[path_start..].split_once(':') {
+            Some((path, sp)) => (&rest[..path_start + path.len()], None, Some(sp)),
+            None => (rest, None, None),
+        },
+    };
+
+    if git_ref.as_ref().is_some_and(|r| r.as_str().is_empty()) || sub_path == Some("") {
+        return None;
+    }
+
+    let authority = &url_rest[..path_start.min(url_rest.len())];
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default();
+    let repo = url_rest
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(".git");
+    if repo.is_empty() {
+        return None;
+    }
+
+    Some(PackageSpec {
+        kind: SourceKind::Git,
+        host: (!host.is_empty()).then(|| host.to_string()),
+        owner: String::new(),
+        repo: repo.to_string(),
+        url: Some(format!("{scheme}://{url_rest}")),
+        path: None,
+        git_ref,
+        version_req: None,
+        sub_path: sub_path.map(str::to_string),
+        integrity: None,
+    })
+}
+
+/// Whether a spec names a directory without the `path:` prefix.
+fn is_local_path(spec: &str) -> bool {
+    spec.starts_with("./") || spec.starts_with("../")
+}
+
+/// Parse `<dir>[:subpath]`. The package is named after the directory's last component.
+fn parse_path_spec(spec: &str) -> Option<PackageSpec> {
+    let (path, sub_path) = match spec.split_once(':') {
+        Some((path, sp)) => (path, Some(sp)),
+        None => (spec, None),
+    };
+    if sub_path == Some("") {
+        return None;
+    }
+    let name = Path::new(path).components().rev().find_map(|c| match c {
+        std::path::Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+        _ => None,
+    })?;
+
+    Some(PackageSpec {
+        kind: SourceKind::Path,
+        host: None,
+        owner: String::new(),
+        repo: name,
+        url: None,
+        path: Some(path.to_string()),
+        git_ref: None,
+        version_req: None,
+        sub_path: sub_path.map(str::to_string),
+        integrity: None,
+    })
+}
+
+/// Archive file extensions, longest first
+const ARCHIVE_EXTENSIONS: [&str; 3] = [".tar.gz", ".tgz", ".zip"];
+
+/// Parse `<archive url>[:subpath]`. The package is named after the file, without its
+/// extension or a trailing version (`skills-1.4.tar.gz` → "skills").
+fn parse_archive_url_spec(spec: &str) -> Option<PackageSpec> {
+    let (scheme, rest) = spec.split_once("://")?;
+    let path_start = rest.find('/')?;
+    let (url, sub_path) = match rest[path_start..].split_once(':') {
+        Some((path, sp)) => (format!("{scheme}://{}", &rest[..path_start + path.len()]), Some(sp)),
+        None => (spec.to_string(), None),
+    };
+    if sub_path == Some("") {
+        return None;
+    }
+
+    let file_path = url.split(['?', '#']).next().unwrap_or_default();
+    let file_name = file_path.rsplit('/').next().unwrap_or_default();
+    let stem = ARCHIVE_EXTENSIONS
+        .iter()
+        .find_map(|ext| file_name.strip_suffix(ext))?;
+    let name = match stem.rsplit_once('-') {
+        Some((name, version))
+            if !name.is_empty()
+                && version.trim_start_matches('v').starts_with(|c: char| c.is_ascii_digit()) =>
+        {
+            name
         }
+        _ => stem,
+    };
+    if name.is_empty() {
+        return None;
+    }
+
+    let host = rest[..path_start].rsplit('@').next().unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default();
+
+    Some(PackageSpec {
+        kind: SourceKind::Archive,
+        host: Some(host.to_string()),
+        owner: String::new(),
+        repo: name.to_string(),
+        url: Some(url),
+        path: None,
+        git_ref: None,
+        version_req: None,
+        sub_path: sub_path.map(str::to_string),
+        integrity: None,
+    })
+}
+
+/// Parse a package specifier given by the user, sending unqualified `owner/repo`
+/// specs to the `default_host` from ~/.copm/config.json when one is configured.
+pub fn resolve_package_spec(spec: &str) -> Result<PackageSpec, CopmError> {
+    let mut parsed = parse_package_spec(spec)?;
+    if parsed.kind == SourceKind::GitHub && parsed.host.is_none() {
+        parsed.host = UserConfig::load_default()
+            .default_host
+            .filter(|h| h != GITHUB_HOST);
     }
+    Ok(parsed)
+}
+
+/// Whether `s` is a URL scheme (`[a-z][a-z0-9+.-]*`), so that a copm.json source can't
+/// smuggle a git option (`git+--upload-pack=…`) in place of the URL.
+fn is_url_scheme(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_ascii_lowercase())
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '+' | '.' | '-'))
+}
 
-    Ok((parts[0].to_string(), parts[1].to_string(), sub_path))
+/// Whether `s` looks like an abbreviated or full commit SHA.
+fn is_commit_sha(s: &str) -> bool {
+    (7..=40).contains(&s.len()) && s.chars().all(|c| c.is_ascii_hexdigit())
 }
 
-/// Download a GitHub repo tarball and extract it to dest_dir.
+/// Download the forge's repository tarball and extract it to dest_dir.
 /// Returns the path to the extracted directory and integrity hash.
-pub async fn fetch_github_tarball(
-    user: &str,
-    repo: &str,
+///
+/// The tarball is decoded while it downloads rather than buffered, and when the spec
+/// has a subpath only that part of the repository is written to disk. The integrity
+/// still covers the whole download.
+pub async fn fetch_tarball(
+    spec: &PackageSpec,
+    git_ref: Option<&GitRef>,
     dest_dir: &Path,
 ) -> Result<FetchResult, CopmError> {
-    let url = format!(
-        "https://api.github.com/repos/{user}/{repo}/tarball/HEAD"
-    );
+    let url = spec.archive_url(git_ref).ok_or_else(|| {
+        CopmError::DownloadFailed(format!("{} has no archive download", spec.source_label()))
+    })?;
 
-    let client = reqwest::Client::builder()
-        .user_agent("copm/0.1.0")
-        .build()?;
+    let mut response = request(spec, &url).await?;
 
-    let response = client
-        .get(&url)
-        .send()
-        .await?
-        .error_for_status()
-        .map_err(|e| CopmError::DownloadFailed(e.to_string()))?;
+    // Extraction is blocking I/O, so it runs on its own thread fed chunk by chunk
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    let unpack = {
+        let dest_dir = dest_dir.to_path_buf();
+        let sub_path = spec.sub_path.clone();
+        let limits = UserConfig::load_default().limits;
+        tokio::task::spawn_blocking(move || {
+            unpack_tarball_filtered(ChunkReader::new(rx), &dest_dir, sub_path.as_deref(), limits)
+        })
+    };
+
+    let mut hasher = Sha256::new();
+    let mut unpacking = true;
+    while let Some(chunk) = response.chunk().await? {
+        hasher.update(&chunk);
+        // The tar reader stops at the end-of-archive marker; hash any trailing bytes anyway
+        if unpacking && tx.send(chunk.to_vec()).await.is_err() {
+            unpacking = false;
+        }
+    }
+    drop(tx);
+    let integrity = format!("sha256-{}", hex::encode(hasher.finalize()));
+
+    let (extracted_dir, rev) = match unpack.await {
+        Ok(result) => result?,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    };
+
+    Ok(FetchResult {
+        extracted_dir,
+        integrity,
+        rev,
+    })
+}
+
+/// A blocking `Read` over chunks sent from an async download.
+struct ChunkReader {
+    rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChunkReader {
+    fn new(rx: tokio::sync::mpsc::Receiver<Vec<u8>>) -> Self {
+        Self {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Download an archive URL and extract it to dest_dir, rejecting it if it doesn't
+/// match the spec's pinned integrity.
+pub async fn fetch_archive(spec: &PackageSpec, dest_dir: &Path) -> Result<FetchResult, CopmError> {
+    let url = spec.url.as_deref().unwrap_or_default();
+    let (bytes, integrity) = download(spec, url).await?;
+
+    if let Some(expected) = &spec.integrity {
+        if *expected != integrity {
+            return Err(CopmError::IntegrityMismatch {
+                pkg: spec.source_label(),
+                expected: expected.clone(),
+                actual: integrity,
+            });
+        }
+    }
+
+    let file_path = url.split(['?', '#']).next().unwrap_or_default();
+    let extracted_dir = if file_path.ends_with(".zip") {
+        unpack_zip(std::io::Cursor::new(&bytes), dest_dir, UserConfig::load_default().limits)?
+    } else {
+        unpack_tarball(&bytes[..], dest_dir)?.0
+    };
+
+    Ok(FetchResult {
+        extracted_dir,
+        integrity,
+        rev: None,
+    })
+}
 
-    let bytes = response.bytes().await?;
+/// GET `url` (with the spec host's token, if any) and return the body with its
+/// `sha256-…` integrity.
+async fn download(spec: &PackageSpec, url: &str) -> Result<(Vec<u8>, String), CopmError> {
+    let bytes = request(spec, url).await?.bytes().await?;
 
     // Compute integrity hash
     let mut hasher = Sha256::new();
@@ -62,68 +585,501 @@ pub async fn fetch_github_tarball(
     let hash = hasher.finalize();
     let integrity = format!("sha256-{}", hex::encode(hash));
 
-    // Extract tarball
-    let decoder = flate2::read::GzDecoder::new(&bytes[..]);
+    Ok((bytes.to_vec(), integrity))
+}
+
+/// Send a GET for `url` with the spec host's token, if any.
+async fn request(spec: &PackageSpec, url: &str) -> Result<reqwest::Response, CopmError> {
+    let client = reqwest::Client::builder()
+        .user_agent("copm/0.1.0")
+        .build()?;
+
+    let mut request = client.get(url);
+    if let Some(token) = auth::token_for_host(spec.host()) {
+        request = request.bearer_auth(token);
+    }
+    request
+        .send()
+        .await?
+        .error_for_status()
+        .map_err(|e| CopmError::DownloadFailed(e.to_string()))
+}
+
+/// Extract a gzipped repository tarball into `dest_dir`.
+///
+/// Returns the package root (the single top-level directory, or `dest_dir` itself when
+/// the archive has several top-level entries) and the commit SHA the tarball was
+/// built from. `git archive` stores the full SHA in the pax global header's "comment";
+/// without one, a SHA at the end of the directory name (GitHub's `owner-repo-<sha>`,
+/// GitLab's `project-<ref>-<sha>`) is used instead.
+///
+/// Entries with absolute or `..` paths fail the extraction with `UnsafeArchive`, as
+/// does going over the [`Limits`] in ~/.copm/config.json. Symlinks, hard links and
+/// device files are skipped.
+pub fn unpack_tarball<R: Read>(
+    reader: R,
+    dest_dir: &Path,
+) -> Result<(PathBuf, Option<String>), CopmError> {
+    unpack_tarball_filtered(reader, dest_dir, None, UserConfig::load_default().limits)
+}
+
+/// [`unpack_tarball`] with explicit `limits`, writing only the entries under `sub_path`
+/// (relative to the tarball's top-level directory, as in a forge's repository tarball)
+/// when one is given.
+pub fn unpack_tarball_filtered<R: Read>(
+    reader: R,
+    dest_dir: &Path,
+    sub_path: Option<&str>,
+    limits: Limits,
+) -> Result<(PathBuf, Option<String>), CopmError> {
+    let decoder = flate2::read::GzDecoder::new(reader);
     let mut archive = tar::Archive::new(decoder);
-    archive.unpack(dest_dir)?;
+    let mut budget = Budget::new(limits);
+    let mut rev = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_pax_global_extensions() {
+            if let Some(extensions) = entry.pax_extensions()? {
+                for ext in extensions.flatten() {
+                    if ext.key() == Ok("comment") {
+                        rev = ext.value().ok().map(|v| v.trim().to_string());
+                    }
+                }
+            }
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        check_entry_path(&path)?;
+        if let Some(sub_path) = sub_path {
+            // Keep the top-level directory itself so the package root is still found
+            let relative: PathBuf = path.components().skip(1).collect();
+            if !relative.as_os_str().is_empty() && !relative.starts_with(sub_path) {
+                continue;
+            }
+        }
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_file() || entry_type.is_contiguous() {
+            budget.charge(entry.size())?;
+        } else if !entry_type.is_dir() {
+            eprintln!("Skipping {} (not a regular file)", path.display());
+            continue;
+        }
+        entry.unpack_in(dest_dir)?;
+    }
 
     // GitHub tarballs extract to a directory like "user-repo-commitsha/"
-    // Find that directory
-    let mut entries = std::fs::read_dir(dest_dir)?;
-    let extracted = entries
-        .next()
-        .ok_or_else(|| CopmError::DownloadFailed("Empty tarball".to_string()))??;
+    let extracted = archive_root(dest_dir)?;
 
-    Ok(FetchResult {
-        extracted_dir: extracted.path(),
-        integrity,
-    })
+    let rev = rev.or_else(|| {
+        let dir_name = extracted.file_name()?.to_string_lossy().to_string();
+        dir_name
+            .rsplit_once('-')
+            .map(|(_, sha)| sha.to_string())
+            .filter(|sha| is_commit_sha(sha))
+    });
+
+    Ok((extracted, rev))
+}
+
+/// Extract a zip archive into `dest_dir` and return the package root, with the same
+/// checks as [`unpack_tarball`].
+pub fn unpack_zip<R: Read + std::io::Seek>(
+    reader: R,
+    dest_dir: &Path,
+    limits: Limits,
+) -> Result<PathBuf, CopmError> {
+    let mut archive = zip::ZipArchive::new(reader)
+        .map_err(|e| CopmError::DownloadFailed(format!("Invalid zip archive: {e}")))?;
+    let mut budget = Budget::new(limits);
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .map_err(|e| CopmError::DownloadFailed(format!("Invalid zip archive: {e}")))?;
+        let Some(relative) = file.enclosed_name() else {
+            return Err(CopmError::UnsafeArchive(format!(
+                "entry {} points outside the package",
+                file.name()
+            )));
+        };
+        let target = dest_dir.join(&relative);
+        if file.is_dir() {
+            std::fs::create_dir_all(&target)?;
+            continue;
+        }
+        if file.is_symlink() {
+            eprintln!("Skipping {} (not a regular file)", relative.display());
+            continue;
+        }
+        budget.charge(file.size())?;
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut out = std::fs::File::create(&target)?;
+        // The declared size is all that was charged, whatever the entry inflates to
+        let size = file.size();
+        std::io::copy(&mut (&mut file).take(size), &mut out)?;
+    }
+    archive_root(dest_dir)
 }
 
-/// Fallback: clone with git
+/// The directory an archive extracted into `dest_dir` should be read from: its only
+/// top-level directory, or `dest_dir` itself when there are several entries.
+fn archive_root(dest_dir: &Path) -> Result<PathBuf, CopmError> {
+    let entries: Vec<_> = std::fs::read_dir(dest_dir)?.collect::<Result<_, _>>()?;
+    match entries.as_slice() {
+        [] => Err(CopmError::DownloadFailed("Empty archive".to_string())),
+        [only] if only.file_type()?.is_dir() => Ok(only.path()),
+        _ => Ok(dest_dir.to_path_buf()),
+    }
+}
+
+/// Compare a freshly computed integrity value with the one recorded in copm.lock.
+///
+/// Returns `None` when the two come from different fetch methods (tarball `sha256-…`
+/// vs git clone `git-…`) and therefore can't be compared.
+pub fn check_integrity(expected: &str, actual: &str) -> Option<bool> {
+    let algorithm = |s: &str| s.split_once('-').map(|(a, _)| a.to_string());
+    if algorithm(expected) != algorithm(actual) {
+        return None;
+    }
+    Some(expected == actual)
+}
+
+/// Fallback: clone with git. When the spec has a subpath, the clone is blobless and
+/// only that path is checked out.
 pub async fn fetch_git_clone(
-    user: &str,
-    repo: &str,
+    spec: &PackageSpec,
+    git_ref: Option<&GitRef>,
     dest_dir: &Path,
 ) -> Result<FetchResult, CopmError> {
-    let url = format!("https://github.com/{user}/{repo}.git");
-    let clone_dir = dest_dir.join(repo);
+    let url = spec.clone_url();
+    let clone_dir = dest_dir.join(&spec.repo);
+
+    // Non-cone patterns, so that a subpath naming a single file works too
+    let sparse_pattern = spec
+        .sub_path
+        .as_deref()
+        .map(|sp| format!("/{}", sp.trim_matches('/')));
+    let partial: &[&str] = match sparse_pattern {
+        Some(_) => &["--filter=blob:none"],
+        None => &[],
+    };
+
+    match (git_ref, &sparse_pattern) {
+        (None | Some(GitRef::Branch(_) | GitRef::Tag(_)), None) => {
+            let mut args = vec!["clone", "--depth", "1"];
+            if let Some(r) = git_ref {
+                args.extend(["--branch", r.as_str()]);
+            }
+            let output = git_command(spec)
+                .args(&args)
+                .arg("--")
+                .arg(&url)
+                .arg(&clone_dir)
+                .output()
+                .await?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(CopmError::DownloadFailed(format!("git clone failed: {stderr}")));
+            }
+        }
+        // A commit can't be passed to `clone --branch`, and a sparse checkout has to be
+        // set up before anything is checked out; fetch the one commit directly instead
+        _ => {
+            std::fs::create_dir_all(&clone_dir)?;
+            run_git(spec, &clone_dir, &["init", "--quiet"]).await?;
+            run_git(spec, &clone_dir, &["remote", "add", "--", "origin", &url]).await?;
+            let target = match git_ref {
+                // Servers only hand out a commit by its full SHA, so fetch the history of
+                // every branch and tag (without file contents) and look it up there
+                Some(GitRef::Rev(rev)) if rev.len() < 40 => {
+                    run_git(
+                        spec,
+                        &clone_dir,
+                        &["fetch", "--quiet", "--filter=blob:none", "--tags", "--", "origin", "+refs/heads/*:refs/remotes/origin/*"],
+                    )
+                    .await?;
+                    let commit = format!("{rev}^{{commit}}");
+                    run_git(spec, &clone_dir, &["rev-parse", "--verify", "--quiet", &commit])
+                        .await
+                        .map_err(|_| {
+                            CopmError::DownloadFailed(format!(
+                                "commit {rev} is not on any branch or tag of {url}; use the full SHA"
+                            ))
+                        })?
+                }
+                _ => {
+                    let mut args = vec!["fetch", "--depth", "1"];
+                    args.extend(partial);
+                    args.extend(["--", "origin", git_ref.map_or("HEAD", GitRef::as_str)]);
+                    run_git(spec, &clone_dir, &args).await?;
+                    "FETCH_HEAD".to_string()
+                }
+            };
+            if let Some(pattern) = &sparse_pattern {
+                run_git(spec, &clone_dir, &["sparse-checkout", "set", "--no-cone", pattern]).await?;
+            }
+            run_git(spec, &clone_dir, &["checkout", "--quiet", &target]).await?;
+        }
+    }
+
+    // Get the commit hash for integrity
+    let rev = run_git(spec, &clone_dir, &["rev-parse", "HEAD"]).await?;
+    let integrity = format!("git-{rev}");
+
+    Ok(FetchResult {
+        extracted_dir: clone_dir,
+        integrity,
+        rev: Some(rev),
+    })
+}
+
+/// Refs advertised by a remote repository, as `(name, commit)` pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteRefs {
+    /// Commit of the default branch
+    pub head: Option<String>,
+    pub branches: Vec<(String, String)>,
+    pub tags: Vec<(String, String)>,
+}
+
+impl RemoteRefs {
+    pub fn branch(&self, name: &str) -> Option<&str> {
+        find_ref(&self.branches, name)
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        find_ref(&self.tags, name)
+    }
+}
+
+fn find_ref<'a>(refs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    refs.iter().find(|(n, _)| n == name).map(|(_, sha)| sha.as_str())
+}
 
-    let output = tokio::process::Command::new("git")
-        .args(["clone", "--depth", "1", &url])
-        .arg(&clone_dir)
+/// List the tags of a GitHub repository as `(tag, commit)` pairs using `git ls-remote`.
+/// Annotated tags are peeled to the commit they point to.
+pub async fn list_remote_tags(spec: &PackageSpec) -> Result<Vec<(String, String)>, CopmError> {
+    let output = ls_remote(spec, &["--tags"]).await?;
+    Ok(parse_ls_remote_tags(&output))
+}
+
+/// List HEAD, branches and tags of a GitHub repository using `git ls-remote`.
+pub async fn list_remote_refs(spec: &PackageSpec) -> Result<RemoteRefs, CopmError> {
+    let output = ls_remote(spec, &[]).await?;
+    Ok(parse_ls_remote(&output))
+}
+
+async fn ls_remote(spec: &PackageSpec, args: &[&str]) -> Result<String, CopmError> {
+    let output = git_command(spec)
+        .arg("ls-remote")
+        .args(args)
+        .arg("--")
+        .arg(spec.clone_url())
         .output()
         .await?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(CopmError::DownloadFailed(format!("git clone failed: {stderr}")));
+        return Err(CopmError::DownloadFailed(format!("git ls-remote failed: {stderr}")));
     }
 
-    // Get the commit hash for integrity
-    let rev_output = tokio::process::Command::new("git")
-        .args(["rev-parse", "HEAD"])
-        .current_dir(&clone_dir)
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Parse full `git ls-remote` output into HEAD, branches and (peeled) tags.
+pub fn parse_ls_remote(output: &str) -> RemoteRefs {
+    let mut refs = RemoteRefs {
+        tags: parse_ls_remote_tags(output),
+        ..Default::default()
+    };
+    for line in output.lines() {
+        let Some((sha, name)) = line.split_once('\t') else {
+            continue;
+        };
+        if name == "HEAD" {
+            refs.head = Some(sha.to_string());
+        } else if let Some(branch) = name.strip_prefix("refs/heads/") {
+            refs.branches.push((branch.to_string(), sha.to_string()));
+        }
+    }
+    refs
+}
+
+/// Parse `git ls-remote --tags` output. A peeled `refs/tags/x^{}` line overrides
+/// the tag object SHA of `refs/tags/x` with the commit it points to.
+pub fn parse_ls_remote_tags(output: &str) -> Vec<(String, String)> {
+    let mut tags: Vec<(String, String)> = Vec::new();
+    for line in output.lines() {
+        let Some((sha, name)) = line.split_once('\t') else {
+            continue;
+        };
+        let Some(tag) = name.strip_prefix("refs/tags/") else {
+            continue;
+        };
+        match tag.strip_suffix("^{}") {
+            Some(peeled) => {
+                if let Some(entry) = tags.iter_mut().find(|(t, _)| t == peeled) {
+                    entry.1 = sha.to_string();
+                } else {
+                    tags.push((peeled.to_string(), sha.to_string()));
+                }
+            }
+            None => tags.push((tag.to_string(), sha.to_string())),
+        }
+    }
+    tags
+}
+
+/// A `git` command that never prompts for credentials and, when a token is
+/// configured for the spec's host, authenticates with it through the environment.
+fn git_command(spec: &PackageSpec) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new("git");
+    command.env("GIT_TERMINAL_PROMPT", "0");
+    let host = spec.host();
+    if let Some(token) = auth::token_for_host(host) {
+        command.envs(auth::git_auth_env(host, spec.kind.git_username(), &token));
+    }
+    command
+}
+
+/// Run a git command for `spec`'s repository in `dir` and return its trimmed stdout.
+async fn run_git(spec: &PackageSpec, dir: &Path, args: &[&str]) -> Result<String, CopmError> {
+    let output = git_command(spec)
+        .args(args)
+        .current_dir(dir)
         .output()
         .await?;
 
-    let rev = String::from_utf8_lossy(&rev_output.stdout).trim().to_string();
-    let integrity = format!("git-{rev}");
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CopmError::DownloadFailed(format!(
+            "git {} failed: {stderr}",
+            args.first().copied().unwrap_or_default()
+        )));
+    }
 
-    Ok(FetchResult {
-        extracted_dir: clone_dir,
-        integrity,
-    })
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
-/// Fetch a package: try tarball first, then fall back to git clone
+/// Fetch a package: use the download cache when the commit is known, otherwise try
+/// the tarball first, then fall back to git clone. Fresh downloads are added to the cache.
+///
+/// With `offline`, only the cache is consulted and a miss is an error.
 pub async fn fetch_package(
-    user: &str,
-    repo: &str,
+    spec: &PackageSpec,
     dest_dir: &Path,
+    offline: bool,
 ) -> Result<FetchResult, CopmError> {
-    match fetch_github_tarball(user, repo, dest_dir).await {
-        Ok(result) => Ok(result),
-        Err(_) => fetch_git_clone(user, repo, dest_dir).await,
+    if let Some(path) = &spec.path {
+        return fetch_local_dir(Path::new(path));
+    }
+    if spec.kind == SourceKind::Archive {
+        if offline {
+            return Err(CopmError::NotCached(spec.to_string()));
+        }
+        return fetch_archive(spec, dest_dir).await;
+    }
+
+    let source = spec.source_label();
+    let cache = Cache::open().ok();
+
+    // Pin the commit up front so it can be looked up in the cache, which is keyed by
+    // full SHAs
+    let git_ref = match &spec.git_ref {
+        Some(GitRef::Rev(rev)) if rev.len() < 40 => {
+            let full = match cache.as_ref().and_then(|c| c.find_rev(&source, rev)) {
+                Some(full) => Some(full),
+                None if offline => None,
+                None => resolve_rev(spec).await,
+            };
+            Some(GitRef::Rev(full.unwrap_or_else(|| rev.clone())))
+        }
+        Some(GitRef::Rev(rev)) => Some(GitRef::Rev(rev.clone())),
+        _ if offline => None,
+        other => match resolve_rev(spec).await {
+            Some(rev) => Some(GitRef::Rev(rev)),
+            None => other.clone(),
+        },
+    };
+
+    if let (Some(cache), Some(GitRef::Rev(rev))) = (&cache, &git_ref) {
+        if let Some(entry) = cache.get_with_subpath(&source, spec.sub_path.as_deref(), rev) {
+            println!("Using cached {source} ({})", &rev[..rev.len().min(7)]);
+            return Ok(FetchResult {
+                extracted_dir: entry.tree_dir(),
+                integrity: entry.meta.integrity,
+                rev: Some(rev.clone()),
+            });
+        }
     }
+
+    if offline {
+        return Err(CopmError::NotCached(spec.to_string()));
+    }
+
+    let git_ref = git_ref.as_ref();
+    let mut result = match fetch_tarball(spec, git_ref, dest_dir).await {
+        Ok(result) => result,
+        // A rejected archive would only be cloned again without the same checks
+        Err(e @ CopmError::UnsafeArchive(_)) => return Err(e),
+        Err(_) => fetch_git_clone(spec, git_ref, dest_dir).await?,
+    };
+    // Some archives (e.g. Gitea's) don't name the commit; fall back to the one requested
+    if let (None, Some(GitRef::Rev(rev))) = (&result.rev, git_ref) {
+        result.rev = Some(rev.clone());
+    }
+
+    // A tree fetched for one subpath is cached apart from the full tree
+    let cache_source = match &spec.sub_path {
+        Some(sp) => partial_source(&source, sp),
+        None => source,
+    };
+    if let (Some(cache), Some(rev)) = (&cache, &result.rev) {
+        if let Err(e) = cache.put(&cache_source, rev, &result.integrity, &result.extracted_dir) {
+            eprintln!("Warning: could not cache {cache_source}: {e}");
+        }
+    }
+
+    Ok(result)
+}
+
+/// Use a local directory in place. Its integrity is the content hash of the tree
+/// (`tree-sha256-…`), and there is no commit.
+pub fn fetch_local_dir(path: &Path) -> Result<FetchResult, CopmError> {
+    if !path.is_dir() {
+        return Err(CopmError::DownloadFailed(format!(
+            "{} is not a directory",
+            path.display()
+        )));
+    }
+    Ok(FetchResult {
+        extracted_dir: path.to_path_buf(),
+        integrity: format!("tree-{}", hash_dir(path)?),
+        rev: None,
+    })
+}
+
+/// Resolve the spec's ref (or the default branch) to a full commit SHA with
+/// `git ls-remote`. Returns `None` if the remote can't be queried.
+async fn resolve_rev(spec: &PackageSpec) -> Option<String> {
+    let refs = list_remote_refs(spec).await.ok()?;
+    let rev = match &spec.git_ref {
+        None => refs.head.as_deref(),
+        Some(GitRef::Tag(tag)) => refs.tag(tag),
+        // `#ref` on a git URL may also name a tag
+        Some(GitRef::Branch(branch)) => refs.branch(branch).or_else(|| refs.tag(branch)),
+        // An abbreviated commit can be expanded when a branch or tag points at it
+        Some(GitRef::Rev(rev)) => {
+            let tips = refs.head.iter().chain(refs.branches.iter().chain(&refs.tags).map(|(_, sha)| sha));
+            let mut matches: Vec<&String> = tips.filter(|sha| sha.starts_with(rev.as_str())).collect();
+            matches.dedup();
+            match matches.as_slice() {
+                [full] => Some(full.as_str()),
+                _ => None,
+            }
+        }
+    };
+    rev.map(str::to_string)
 }
@@ -0,0 +1,60 @@
+use std::path::{Component, Path};
+
+use crate::config::user_config::{Limits, UserConfig};
+use crate::error::CopmError;
+
+/// Files and bytes written so far for one package, checked against [`Limits`].
+#[derive(Debug, Clone)]
+pub struct Budget {
+    limits: Limits,
+    files: u64,
+    bytes: u64,
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            files: 0,
+            bytes: 0,
+        }
+    }
+
+    /// A budget with the limits from ~/.copm/config.json.
+    pub fn from_user_config() -> Self {
+        Self::new(UserConfig::load_default().limits)
+    }
+
+    /// Account for one more file of `size` bytes.
+    pub fn charge(&mut self, size: u64) -> Result<(), CopmError> {
+        self.files += 1;
+        self.bytes = self.bytes.saturating_add(size);
+        if self.files > self.limits.max_files {
+            return Err(CopmError::UnsafeArchive(format!(
+                "more than {} files (limits.max_files in ~/.copm/config.json)",
+                self.limits.max_files
+            )));
+        }
+        if self.bytes > self.limits.max_total_size {
+            return Err(CopmError::UnsafeArchive(format!(
+                "more than {} bytes (limits.max_total_size in ~/.copm/config.json)",
+                self.limits.max_total_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Reject an archive entry path that is absolute or climbs out with `..`.
+pub fn check_entry_path(path: &Path) -> Result<(), CopmError> {
+    let escapes = path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    if escapes {
+        return Err(CopmError::UnsafeArchive(format!(
+            "entry {} points outside the package",
+            path.display()
+        )));
+    }
+    Ok(())
+}
@@ -1 +1,6 @@
+pub mod auth;
+pub mod cache;
 pub mod git;
+pub mod limits;
+pub mod source;
+pub mod version;
@@ -0,0 +1,151 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// The forge a package is hosted on, chosen by the specifier prefix
+/// (`gitlab:`, `bitbucket:`, `gitea:`, `git+<url>`, `path:`, an archive URL; none means
+/// GitHub) and recorded as `source.type` in copm.lock.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    #[default]
+    GitHub,
+    GitLab,
+    Bitbucket,
+    Gitea,
+    /// Any git URL (`git+https://…`, `git+ssh://…`, `git+file://…`); clone only
+    Git,
+    /// A directory on disk (`./my-skill`, `path:../shared`), used as is
+    Path,
+    /// A `.tar.gz` / `.tgz` / `.zip` download (`https://example.com/skills-1.4.tar.gz`)
+    Archive,
+}
+
+impl SourceKind {
+    pub const ALL: [SourceKind; 7] = [
+        SourceKind::GitHub,
+        SourceKind::GitLab,
+        SourceKind::Bitbucket,
+        SourceKind::Gitea,
+        SourceKind::Git,
+        SourceKind::Path,
+        SourceKind::Archive,
+    ];
+
+    /// Kinds selected with a `<kind>:` specifier prefix
+    pub const PREFIXED: [SourceKind; 4] = [
+        SourceKind::GitHub,
+        SourceKind::GitLab,
+        SourceKind::Bitbucket,
+        SourceKind::Gitea,
+    ];
+
+    /// Specifier prefix / lock `type` value
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SourceKind::GitHub => "github",
+            SourceKind::GitLab => "gitlab",
+            SourceKind::Bitbucket => "bitbucket",
+            SourceKind::Gitea => "gitea",
+            SourceKind::Git => "git",
+            SourceKind::Path => "path",
+            SourceKind::Archive => "archive",
+        }
+    }
+
+    /// The kind recorded as `name` in copm.lock.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.as_str() == name)
+    }
+
+    /// Whether packages of this kind are pinned to a commit. Local directories and
+    /// archive downloads are pinned by their integrity hash instead.
+    pub fn has_commits(self) -> bool {
+        !matches!(self, SourceKind::Path | SourceKind::Archive)
+    }
+
+    pub fn from_prefix(prefix: &str) -> Option<Self> {
+        Self::PREFIXED.into_iter().find(|k| k.as_str() == prefix)
+    }
+
+    /// Public host used when the specifier doesn't name one. Gitea, git URLs and local
+    /// directories have none.
+    pub fn default_host(self) -> Option<&'static str> {
+        match self {
+            SourceKind::GitHub => Some("github.com"),
+            SourceKind::GitLab => Some("gitlab.com"),
+            SourceKind::Bitbucket => Some("bitbucket.org"),
+            SourceKind::Gitea | SourceKind::Git | SourceKind::Path | SourceKind::Archive => None,
+        }
+    }
+
+    /// REST API base on `host`.
+    pub fn api_base(self, host: &str) -> String {
+        match self {
+            SourceKind::GitHub if host == "github.com" => "https://api.github.com".to_string(),
+            SourceKind::GitHub => format!("https://{host}/api/v3"),
+            SourceKind::GitLab => format!("https://{host}/api/v4"),
+            SourceKind::Bitbucket => "https://api.bitbucket.org/2.0".to_string(),
+            SourceKind::Gitea => format!("https://{host}/api/v1"),
+            // Plain git servers have no REST API
+            SourceKind::Git | SourceKind::Path | SourceKind::Archive => String::new(),
+        }
+    }
+
+    /// URL of a gzipped tarball of `owner/repo` at `reference` (the default branch when
+    /// `None`), or `None` for plain git URLs, which can only be cloned, local directories
+    /// and archive downloads (whose URL is the archive itself).
+    pub fn archive_url(
+        self,
+        api_base: &str,
+        host: &str,
+        owner: &str,
+        repo: &str,
+        reference: Option<&str>,
+    ) -> Option<String> {
+        let url = match self {
+            SourceKind::GitHub => format!(
+                "{api_base}/repos/{owner}/{repo}/tarball/{}",
+                reference.unwrap_or("HEAD")
+            ),
+            // Nested groups are part of the project path, which must be URL-encoded
+            SourceKind::GitLab => {
+                let project = format!("{owner}/{repo}").replace('/', "%2F");
+                let url = format!("{api_base}/projects/{project}/repository/archive.tar.gz");
+                match reference {
+                    Some(r) => format!("{url}?sha={r}"),
+                    None => url,
+                }
+            }
+            SourceKind::Bitbucket => format!(
+                "https://{host}/{owner}/{repo}/get/{}.tar.gz",
+                reference.unwrap_or("HEAD")
+            ),
+            SourceKind::Gitea => format!(
+                "{api_base}/repos/{owner}/{repo}/archive/{}.tar.gz",
+                reference.unwrap_or("HEAD")
+            ),
+            SourceKind::Git | SourceKind::Path | SourceKind::Archive => return None,
+        };
+        Some(url)
+    }
+
+    /// User name that goes with an access token in git's HTTP basic auth.
+    pub fn git_username(self) -> &'static str {
+        match self {
+            SourceKind::GitHub
+            | SourceKind::Gitea
+            | SourceKind::Git
+            | SourceKind::Path
+            | SourceKind::Archive => "x-access-token",
+            SourceKind::GitLab => "oauth2",
+            SourceKind::Bitbucket => "x-token-auth",
+        }
+    }
+}
+
+impl fmt::Display for SourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,40 @@
+use semver::{Version, VersionReq};
+
+use crate::error::CopmError;
+
+/// A git tag that satisfied a version requirement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedVersion {
+    pub version: Version,
+    pub tag: String,
+    /// Commit the tag points to
+    pub rev: String,
+}
+
+/// Interpret a git tag as a semantic version ("v1.2.0" and "1.2.0" both → 1.2.0).
+pub fn version_from_tag(tag: &str) -> Option<Version> {
+    let raw = tag.strip_prefix('v').unwrap_or(tag);
+    Version::parse(raw).ok()
+}
+
+/// Parse a version requirement such as "^1.2", "~0.3" or ">=1, <2".
+pub fn parse_version_req(req: &str) -> Result<VersionReq, CopmError> {
+    VersionReq::parse(req).map_err(|e| CopmError::InvalidVersionReq(format!("{req}: {e}")))
+}
+
+/// Pick the highest tag whose version satisfies `req`.
+///
+/// `tags` is a list of `(tag, commit)` pairs as returned by `list_remote_tags()`.
+/// Tags that are not valid semver are ignored.
+pub fn resolve_version(tags: &[(String, String)], req: &VersionReq) -> Option<ResolvedVersion> {
+    tags.iter()
+        .filter_map(|(tag, rev)| {
+            version_from_tag(tag).map(|version| ResolvedVersion {
+                version,
+                tag: tag.clone(),
+                rev: rev.clone(),
+            })
+        })
+        .filter(|r| req.matches(&r.version))
+        .max_by(|a, b| a.version.cmp(&b.version))
+}
@@ -1,8 +1,7 @@
 use std::path::{Path, PathBuf};
 
-use walkdir::WalkDir;
-
 use crate::error::CopmError;
+use crate::installer::Plan;
 use crate::paths;
 
 // ── copilot-instructions ──────────────────────────────────────────────────────
@@ -13,10 +12,11 @@ use crate::paths;
 pub fn install_instructions(
     source_dir: &Path,
     global: bool,
-) -> Result<Vec<PathBuf>, CopmError> {
+    plan: &mut Plan,
+) -> Result<(), CopmError> {
     if global {
         // Global copilot-instructions is not a defined location; skip
-        return Ok(vec![]);
+        return Ok(());
     }
 
     // Look for copilot-instructions.md at source root, then any single .md
@@ -47,12 +47,8 @@ pub fn install_instructions(
         }
     };
 
-    let dest = paths::copilot_instructions_path();
-    if let Some(parent) = dest.parent() {
-        std::fs::create_dir_all(parent)?;
-    }
-    std::fs::copy(&source_file, &dest)?;
-    Ok(vec![dest])
+    plan.add(&source_file, paths::copilot_instructions_path());
+    Ok(())
 }
 
 pub fn uninstall_instructions() -> Result<(), CopmError> {
@@ -69,23 +65,19 @@ pub fn uninstall_instructions() -> Result<(), CopmError> {
 pub fn install_file_collection(
     source_dir: &Path,
     suffix: &str,
-    dest_dir: &PathBuf,
-) -> Result<Vec<PathBuf>, CopmError> {
-    std::fs::create_dir_all(dest_dir)?;
-    let mut installed = Vec::new();
-
-    for entry in std::fs::read_dir(source_dir)? {
-        let entry = entry?;
+    dest_dir: &Path,
+    plan: &mut Plan,
+) -> Result<(), CopmError> {
+    let mut entries: Vec<_> = std::fs::read_dir(source_dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
         let name = entry.file_name();
         let name_str = name.to_string_lossy();
         if entry.file_type()?.is_file() && name_str.ends_with(suffix) {
-            let dest_file = dest_dir.join(&name);
-            std::fs::copy(entry.path(), &dest_file)?;
-            installed.push(dest_file);
+            plan.add(&entry.path(), dest_dir.join(&name));
         }
     }
-
-    Ok(installed)
+    Ok(())
 }
 
 // ── copilot-custom-instructions ───────────────────────────────────────────────
@@ -93,13 +85,14 @@ pub fn install_file_collection(
 pub fn install_custom_instructions(
     source_dir: &Path,
     global: bool,
-) -> Result<Vec<PathBuf>, CopmError> {
+    plan: &mut Plan,
+) -> Result<(), CopmError> {
     let dest_dir = if global {
         paths::global_copilot_instructions_dir()?
     } else {
         paths::copilot_custom_instructions_dir()
     };
-    install_file_collection(source_dir, ".instructions.md", &dest_dir)
+    install_file_collection(source_dir, ".instructions.md", &dest_dir, plan)
 }
 
 pub fn uninstall_custom_instructions(name: &str) -> Result<(), CopmError> {
@@ -142,12 +135,12 @@ pub fn list_custom_instructions() -> Result<Vec<String>, CopmError> {
 
 // ── copilot-agents ────────────────────────────────────────────────────────────
 
-pub fn install_agents(source_dir: &Path, global: bool) -> Result<Vec<PathBuf>, CopmError> {
+pub fn install_agents(source_dir: &Path, global: bool, plan: &mut Plan) -> Result<(), CopmError> {
     // Global agent path is not standardized yet; use local only for now
     if global {
-        return Ok(vec![]);
+        return Ok(());
     }
-    install_file_collection(source_dir, ".agent.md", &paths::copilot_agents_dir())
+    install_file_collection(source_dir, ".agent.md", &paths::copilot_agents_dir(), plan)
 }
 
 pub fn list_agents() -> Result<Vec<String>, CopmError> {
@@ -169,11 +162,11 @@ pub fn list_agents() -> Result<Vec<String>, CopmError> {
 
 // ── copilot-prompts ───────────────────────────────────────────────────────────
 
-pub fn install_prompts(source_dir: &Path, global: bool) -> Result<Vec<PathBuf>, CopmError> {
+pub fn install_prompts(source_dir: &Path, global: bool, plan: &mut Plan) -> Result<(), CopmError> {
     if global {
-        return Ok(vec![]);
+        return Ok(());
     }
-    install_file_collection(source_dir, ".prompt.md", &paths::copilot_prompts_dir())
+    install_file_collection(source_dir, ".prompt.md", &paths::copilot_prompts_dir(), plan)
 }
 
 pub fn list_prompts() -> Result<Vec<String>, CopmError> {
@@ -209,12 +202,11 @@ pub fn install_skill(
     skill_name: &str,
     tools: &[String],
     global: bool,
-) -> Result<Vec<PathBuf>, CopmError> {
-    let mut installed = Vec::new();
-
+    plan: &mut Plan,
+) -> Result<(), CopmError> {
     if source_dir.join("SKILL.md").exists() {
         // Single skill
-        install_single_skill(source_dir, skill_name, tools, global, &mut installed)?;
+        install_single_skill(source_dir, skill_name, tools, global, plan)?;
     } else {
         // Collection: install each subdir that contains SKILL.md
         if let Ok(entries) = std::fs::read_dir(source_dir) {
@@ -229,12 +221,12 @@ pub fn install_skill(
 
             for entry in subdirs {
                 let name = entry.file_name().to_string_lossy().to_string();
-                install_single_skill(&entry.path(), &name, tools, global, &mut installed)?;
+                install_single_skill(&entry.path(), &name, tools, global, plan)?;
             }
         }
     }
 
-    Ok(installed)
+    Ok(())
 }
 
 fn install_single_skill(
@@ -242,7 +234,7 @@ fn install_single_skill(
     name: &str,
     tools: &[String],
     global: bool,
-    installed: &mut Vec<PathBuf>,
+    plan: &mut Plan,
 ) -> Result<(), CopmError> {
     for tool in tools {
         let dest = match tool.as_str() {
@@ -263,14 +255,7 @@ fn install_single_skill(
             _ => continue,
         };
 
-        if dest.exists() {
-            std::fs::remove_dir_all(&dest)?;
-        }
-        if let Some(parent) = dest.parent() {
-            std::fs::create_dir_all(parent)?;
-        }
-        copy_dir_recursive(skill_dir, &dest)?;
-        installed.push(dest);
+        plan.add(skill_dir, dest);
     }
     Ok(())
 }
@@ -280,13 +265,14 @@ fn install_single_skill(
 pub fn install_claude_command(
     source_dir: &Path,
     global: bool,
-) -> Result<Vec<PathBuf>, CopmError> {
+    plan: &mut Plan,
+) -> Result<(), CopmError> {
     let dest_dir = if global {
         paths::global_claude_commands_dir()?
     } else {
         paths::local_claude_commands_dir()
     };
-    install_file_collection(source_dir, ".md", &dest_dir)
+    install_file_collection(source_dir, ".md", &dest_dir, plan)
 }
 
 pub fn list_claude_commands(global: bool) -> Result<Vec<String>, CopmError> {
@@ -313,33 +299,64 @@ pub fn list_claude_commands(global: bool) -> Result<Vec<String>, CopmError> {
 // ── File-based uninstall ──────────────────────────────────────────────────────
 
 /// Remove all paths listed in `files` (supports both files and directories).
+///
+/// `files` comes from copm.lock, which can be edited by hand, so anything outside the
+/// directories copm installs into ([`paths::managed_roots`]) is reported and left alone.
 pub fn uninstall_by_files(files: &[String]) -> Result<(), CopmError> {
-    for file in files {
-        let path = PathBuf::from(file);
-        if path.is_dir() {
+    for path in uninstall_by_files_within(files, &paths::managed_roots())? {
+        eprintln!(
+            "Skipping {}: outside the directories copm installs into",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// [`uninstall_by_files`] limited to `roots`. Returns the paths that were skipped.
+pub fn uninstall_by_files_within(
+    files: &[String],
+    roots: &[PathBuf],
+) -> Result<Vec<PathBuf>, CopmError> {
+    let (managed, skipped) = partition_managed(files, roots);
+    for path in managed {
+        if path.symlink_metadata()?.is_dir() {
             std::fs::remove_dir_all(&path)?;
-        } else if path.exists() {
+        } else {
             std::fs::remove_file(&path)?;
         }
     }
-    Ok(())
+    Ok(skipped)
 }
 
-// ── Utilities ─────────────────────────────────────────────────────────────────
-
-fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<(), CopmError> {
-    std::fs::create_dir_all(dst)?;
-    for entry in WalkDir::new(src).min_depth(1) {
-        let entry = entry.map_err(|e| {
-            CopmError::Io(std::io::Error::other(e.to_string()))
-        })?;
-        let relative = entry.path().strip_prefix(src).unwrap();
-        let dest_path = dst.join(relative);
-        if entry.file_type().is_dir() {
-            std::fs::create_dir_all(&dest_path)?;
+/// Split the existing `files` into those inside one of `roots` and those outside.
+/// Files that are already gone are left out of both.
+pub fn partition_managed(files: &[String], roots: &[PathBuf]) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let roots: Vec<PathBuf> = roots.iter().filter_map(|r| r.canonicalize().ok()).collect();
+    let mut managed = Vec::new();
+    let mut skipped = Vec::new();
+    for file in files {
+        let path = PathBuf::from(file);
+        if path.symlink_metadata().is_err() {
+            continue; // Already gone
+        }
+        // Resolve the parent only, so that a symlink is judged (and removed) as itself
+        let resolved = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => {
+                let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+                parent.canonicalize().ok().map(|p| p.join(name))
+            }
+            _ => None,
+        };
+        let inside = resolved.is_some_and(|resolved| {
+            roots.iter().any(|root| {
+                resolved.starts_with(root) && (resolved != *root || root.is_file())
+            })
+        });
+        if inside {
+            managed.push(path);
         } else {
-            std::fs::copy(entry.path(), &dest_path)?;
+            skipped.push(path);
         }
     }
-    Ok(())
+    (managed, skipped)
 }
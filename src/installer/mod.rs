@@ -1,36 +1,157 @@
 pub mod claude_plugin;
 pub mod copilot;
+pub mod transaction;
 
 use std::path::{Path, PathBuf};
 
+use walkdir::WalkDir;
+
+use crate::config::lock::CopmLock;
 use crate::error::CopmError;
+use crate::fetcher::limits::Budget;
 use crate::manifest::package_manifest::{PackageManifest, Target};
 use crate::paths;
 
+use transaction::Transaction;
+
 /// Install all targets from a manifest, dispatching to the appropriate installer.
 ///
 /// - `name`: The package name (used for skill directory names).
 /// - `tools`: The tools configured in copm.json (affects skill install destinations).
 /// - `global`: Whether to install globally.
+/// - `owners`: Which package installed each existing path (from copm.lock or
+///   ~/.copm/installed.json), used to find destinations this package doesn't own.
+///
+/// - `tx`: The transaction the copies are made in; rolling it back undoes them.
+///
+/// Nothing is written when a conflict is found under [`ConflictPolicy::Fail`].
 ///
 /// Returns `(installed_paths, target_types)`.
+#[allow(clippy::too_many_arguments)]
 pub fn install_targets(
     source_dir: &Path,
     manifest: &PackageManifest,
     name: &str,
     tools: &[String],
     global: bool,
+    owners: &CopmLock,
+    policy: ConflictPolicy,
+    tx: &mut Transaction,
 ) -> Result<(Vec<PathBuf>, Vec<String>), CopmError> {
-    let mut all_paths = Vec::new();
+    let mut plan = Plan::default();
     let mut target_types = Vec::new();
 
     for target in &manifest.targets {
-        let paths = install_target(source_dir, target, name, tools, global)?;
+        install_target(source_dir, target, name, tools, global, &mut plan)?;
         target_types.push(target.target_type.clone());
-        all_paths.extend(paths);
     }
 
-    Ok((all_paths, target_types))
+    plan.resolve_conflicts(owners, name, policy)?;
+
+    // One budget for the whole package, so the limits can't be dodged by splitting it up
+    let mut budget = Budget::from_user_config();
+    Ok((tx.apply(&plan, &mut budget)?, target_types))
+}
+
+/// What to do when a destination exists but wasn't installed by the package being installed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Stop with an error listing every clash
+    #[default]
+    Fail,
+    /// Move the existing file to a backup (`.copm-backup/` or `~/.copm/backups/`) and install over it
+    Force,
+    /// Leave the existing file alone and install everything else
+    SkipExisting,
+}
+
+/// A destination an install would overwrite.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub path: PathBuf,
+    /// Package that installed it, or `None` for a file copm didn't create
+    pub owner: Option<String>,
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.owner {
+            Some(owner) => write!(f, "{} (installed by {owner})", self.path.display()),
+            None => write!(f, "{} (not installed by copm)", self.path.display()),
+        }
+    }
+}
+
+/// A file or directory to copy into place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    /// Move what's at `dest` to a backup directory instead of replacing it
+    pub backup: bool,
+}
+
+/// Everything an install will copy, collected before anything is written.
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    pub placements: Vec<Placement>,
+}
+
+impl Plan {
+    pub fn add(&mut self, source: &Path, dest: PathBuf) {
+        self.placements.push(Placement {
+            source: source.to_path_buf(),
+            dest,
+            backup: false,
+        });
+    }
+
+    /// Destinations that already exist and aren't owned by `package` in `owners`.
+    pub fn conflicts(&self, owners: &CopmLock, package: &str) -> Vec<Conflict> {
+        self.placements
+            .iter()
+            .filter(|p| p.dest.symlink_metadata().is_ok())
+            .filter_map(|p| {
+                let owner = owners.owner_of(&p.dest);
+                (owner != Some(package)).then(|| Conflict {
+                    path: p.dest.clone(),
+                    owner: owner.map(str::to_string),
+                })
+            })
+            .collect()
+    }
+
+    /// Deal with [`Plan::conflicts`] according to `policy`: fail listing them, mark them
+    /// to be moved aside to a backup when applied, or drop them from the plan.
+    pub fn resolve_conflicts(
+        &mut self,
+        owners: &CopmLock,
+        package: &str,
+        policy: ConflictPolicy,
+    ) -> Result<(), CopmError> {
+        let conflicts = self.conflicts(owners, package);
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+        match policy {
+            ConflictPolicy::Fail => {
+                let list = conflicts.iter().map(|c| format!("  {c}")).collect::<Vec<_>>();
+                return Err(CopmError::InstallConflict(list.join("\n")));
+            }
+            ConflictPolicy::SkipExisting => {
+                for conflict in &conflicts {
+                    println!("  Skipping existing {}", conflict.path.display());
+                }
+                self.placements.retain(|p| !conflicts.iter().any(|c| c.path == p.dest));
+            }
+            ConflictPolicy::Force => {
+                for placement in &mut self.placements {
+                    placement.backup |= conflicts.iter().any(|c| c.path == placement.dest);
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 fn install_target(
@@ -39,7 +160,8 @@ fn install_target(
     name: &str,
     tools: &[String],
     global: bool,
-) -> Result<Vec<PathBuf>, CopmError> {
+    plan: &mut Plan,
+) -> Result<(), CopmError> {
     let target_path = if target.path == "." {
         source_dir.to_path_buf()
     } else {
@@ -48,22 +170,21 @@ fn install_target(
 
     // Single-file install
     if target_path.is_file() {
-        return install_single_file(&target_path, &target.target_type, global);
+        return install_single_file(&target_path, &target.target_type, global, plan);
     }
 
     // Directory install
     match target.target_type.as_str() {
         "claude-plugin" => {
             // Legacy: kept for backward compatibility
-            let path = claude_plugin::install_plugin_dir(&target_path, name, global)?;
-            Ok(vec![path])
+            claude_plugin::install_plugin_dir(&target_path, name, global, plan)
         }
-        "copilot-instructions" => copilot::install_instructions(&target_path, global),
-        "copilot-custom-instructions" => copilot::install_custom_instructions(&target_path, global),
-        "copilot-agents" => copilot::install_agents(&target_path, global),
-        "copilot-prompts" => copilot::install_prompts(&target_path, global),
-        "skill" => copilot::install_skill(&target_path, name, tools, global),
-        "claude-command" => copilot::install_claude_command(&target_path, global),
+        "copilot-instructions" => copilot::install_instructions(&target_path, global, plan),
+        "copilot-custom-instructions" => copilot::install_custom_instructions(&target_path, global, plan),
+        "copilot-agents" => copilot::install_agents(&target_path, global, plan),
+        "copilot-prompts" => copilot::install_prompts(&target_path, global, plan),
+        "skill" => copilot::install_skill(&target_path, name, tools, global, plan),
+        "claude-command" => copilot::install_claude_command(&target_path, global, plan),
         other => Err(CopmError::UnsupportedTargetType(other.to_string())),
     }
 }
@@ -73,20 +194,16 @@ fn install_single_file(
     file_path: &Path,
     target_type: &str,
     global: bool,
-) -> Result<Vec<PathBuf>, CopmError> {
+    plan: &mut Plan,
+) -> Result<(), CopmError> {
     let file_name = file_path.file_name().unwrap();
 
     // copilot-instructions always installs to a fixed path
     if target_type == "copilot-instructions" {
-        if global {
-            return Ok(vec![]);
-        }
-        let dest = paths::copilot_instructions_path();
-        if let Some(parent) = dest.parent() {
-            std::fs::create_dir_all(parent)?;
+        if !global {
+            plan.add(file_path, paths::copilot_instructions_path());
         }
-        std::fs::copy(file_path, &dest)?;
-        return Ok(vec![dest]);
+        return Ok(());
     }
 
     let dest_dir = match target_type {
@@ -99,13 +216,13 @@ fn install_single_file(
         }
         "copilot-agents" => {
             if global {
-                return Ok(vec![]);
+                return Ok(());
             }
             paths::copilot_agents_dir()
         }
         "copilot-prompts" => {
             if global {
-                return Ok(vec![]);
+                return Ok(());
             }
             paths::copilot_prompts_dir()
         }
@@ -119,10 +236,51 @@ fn install_single_file(
         other => return Err(CopmError::UnsupportedTargetType(other.to_string())),
     };
 
-    std::fs::create_dir_all(&dest_dir)?;
-    let dest = dest_dir.join(file_name);
-    std::fs::copy(file_path, &dest)?;
-    Ok(vec![dest])
+    plan.add(file_path, dest_dir.join(file_name));
+    Ok(())
+}
+
+/// Copy one file from a package, refusing symlinks and special files so that nothing
+/// outside the package (e.g. a link to `~/.ssh`) can be installed. The file is charged
+/// to `budget`.
+pub fn copy_file(src: &Path, dst: &Path, budget: &mut Budget) -> Result<u64, CopmError> {
+    let metadata = src.symlink_metadata()?;
+    if !metadata.file_type().is_file() {
+        return Err(CopmError::UnsafeArchive(format!(
+            "{} is not a regular file",
+            src.display()
+        )));
+    }
+    budget.charge(metadata.len())?;
+    Ok(std::fs::copy(src, dst)?)
+}
+
+/// Copy a package directory. Symlinks and special files inside it are skipped, and the
+/// copy stops once the package goes over `budget`.
+pub fn copy_dir(src: &Path, dst: &Path, budget: &mut Budget) -> Result<(), CopmError> {
+    if !src.symlink_metadata()?.file_type().is_dir() {
+        return Err(CopmError::UnsafeArchive(format!(
+            "{} is not a directory",
+            src.display()
+        )));
+    }
+    std::fs::create_dir_all(dst)?;
+    for entry in WalkDir::new(src).min_depth(1) {
+        let entry = entry.map_err(|e| {
+            CopmError::Io(std::io::Error::other(e.to_string()))
+        })?;
+        let relative = entry.path().strip_prefix(src).unwrap();
+        let dest_path = dst.join(relative);
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&dest_path)?;
+        } else if entry.file_type().is_file() {
+            budget.charge(entry.metadata().map_or(0, |m| m.len()))?;
+            std::fs::copy(entry.path(), &dest_path)?;
+        } else {
+            eprintln!("Skipping {} (not a regular file)", entry.path().display());
+        }
+    }
+    Ok(())
 }
 
 /// Uninstall a package using its recorded `installed_files` if available,
@@ -0,0 +1,255 @@
+use std::path::{Component, Path, PathBuf};
+
+use tempfile::TempDir;
+
+use crate::config::write_atomic;
+use crate::error::CopmError;
+use crate::fetcher::cache::now;
+use crate::fetcher::limits::Budget;
+use crate::installer::{copy_dir, copy_file, Plan};
+use crate::paths;
+
+/// A step to take back when a transaction is rolled back.
+#[derive(Debug)]
+enum Undo {
+    /// Delete something this transaction created
+    Remove(PathBuf),
+    /// Move something this transaction moved aside back to where it was
+    Restore { from: PathBuf, to: PathBuf },
+    /// Put back a file's previous contents, or delete it if it didn't exist
+    Rewrite { path: PathBuf, contents: Option<Vec<u8>> },
+}
+
+impl Undo {
+    fn revert(&self) -> std::io::Result<()> {
+        match self {
+            Undo::Remove(path) => remove(path),
+            Undo::Restore { from, to } => {
+                remove(to)?;
+                std::fs::rename(from, to)
+            }
+            Undo::Rewrite { path, contents: Some(contents) } => {
+                write_atomic(path, contents).map_err(std::io::Error::other)
+            }
+            Undo::Rewrite { path, contents: None } => remove(path),
+        }
+    }
+}
+
+/// File system changes made by one `copm install` or `copm update`, undone unless committed.
+///
+/// Package files are first copied into staging directories next to their destinations,
+/// so a failed copy leaves every destination untouched. They are then renamed into
+/// place, moving whatever was there aside. Rolling back, explicitly or by dropping the
+/// transaction, reverses every step, including writes to files registered with
+/// [`Transaction::track`] (copm.json and copm.lock).
+#[derive(Debug, Default)]
+pub struct Transaction {
+    /// Staging directory for each destination directory
+    staging: Vec<(PathBuf, TempDir)>,
+    undo: Vec<Undo>,
+    /// Names handed out in the staging directories
+    next: usize,
+    /// Where replaced files are moved by `--force`, when set with [`Transaction::with_backup_dir`]
+    backup_dir: Option<PathBuf>,
+    /// Time of the first backup, naming this transaction's backup directories
+    backup_time: Option<u64>,
+    done: bool,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move replaced files under `dir` (keeping their full path) instead of the default
+    /// backup directories.
+    pub fn with_backup_dir(dir: PathBuf) -> Self {
+        let mut tx = Self::default();
+        tx.backup_dir = Some(dir);
+        tx
+    }
+
+    /// Copy every placement of `plan` into place, charging every file to `budget`.
+    /// Returns the destinations.
+    pub fn apply(&mut self, plan: &Plan, budget: &mut Budget) -> Result<Vec<PathBuf>, CopmError> {
+        // Stage everything before touching any destination
+        let mut staged = Vec::with_capacity(plan.placements.len());
+        for placement in &plan.placements {
+            let path = self.staging_path(&placement.dest)?;
+            if placement.source.symlink_metadata()?.is_dir() {
+                copy_dir(&placement.source, &path, budget)?;
+            } else {
+                copy_file(&placement.source, &path, budget)?;
+            }
+            staged.push(path);
+        }
+
+        for (placement, staged) in plan.placements.iter().zip(staged) {
+            let dest = &placement.dest;
+            if dest.symlink_metadata().is_ok() {
+                let aside = if placement.backup {
+                    self.backup_path(dest)?
+                } else {
+                    self.staging_path(dest)?
+                };
+                std::fs::rename(dest, &aside)?;
+                if placement.backup {
+                    println!("  Backed up {} to {}", dest.display(), aside.display());
+                }
+                self.undo.push(Undo::Restore {
+                    from: aside,
+                    to: dest.clone(),
+                });
+            }
+            std::fs::rename(&staged, dest)?;
+            self.undo.push(Undo::Remove(dest.clone()));
+        }
+
+        Ok(plan.placements.iter().map(|p| p.dest.clone()).collect())
+    }
+
+    /// Remove `path` (a file or directory), keeping it aside until the transaction
+    /// commits so that a rollback can put it back.
+    pub fn remove(&mut self, path: &Path) -> Result<(), CopmError> {
+        if path.symlink_metadata().is_err() {
+            return Ok(()); // Already gone
+        }
+        let aside = self.staging_path(path)?;
+        std::fs::rename(path, &aside)?;
+        self.undo.push(Undo::Restore {
+            from: aside,
+            to: path.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    /// Remember the current contents of `path` (or that it doesn't exist) so that a
+    /// rollback can restore them. Call before writing to it.
+    pub fn track(&mut self, path: &Path) -> Result<(), CopmError> {
+        let tracked = self.undo.iter().any(|u| matches!(u, Undo::Rewrite { path: p, .. } if p == path));
+        if !tracked {
+            let contents = match std::fs::read(path) {
+                Ok(contents) => Some(contents),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+            self.undo.push(Undo::Rewrite {
+                path: path.to_path_buf(),
+                contents,
+            });
+        }
+        Ok(())
+    }
+
+    /// Keep every change.
+    pub fn commit(mut self) {
+        self.done = true;
+        self.undo.clear();
+    }
+
+    /// Undo every change, most recent first.
+    pub fn rollback(mut self) {
+        self.revert();
+    }
+
+    fn revert(&mut self) {
+        self.done = true;
+        while let Some(step) = self.undo.pop() {
+            if let Err(e) = step.revert() {
+                eprintln!("Warning: could not roll back {step:?}: {e}");
+            }
+        }
+    }
+
+    /// A fresh path in the staging directory beside `dest`, creating its parent
+    /// directories (removed again on rollback) as needed.
+    fn staging_path(&mut self, dest: &Path) -> Result<PathBuf, CopmError> {
+        let parent = self.create_parent(dest)?;
+        let index = match self.staging.iter().position(|(p, _)| *p == parent) {
+            Some(index) => index,
+            None => {
+                let dir = tempfile::Builder::new()
+                    .prefix(".copm-staging-")
+                    .tempdir_in(&parent)?;
+                self.staging.push((parent, dir));
+                self.staging.len() - 1
+            }
+        };
+        self.next += 1;
+        Ok(self.staging[index].1.path().join(self.next.to_string()))
+    }
+
+    /// Where to move `dest` aside under `--force`: `.copm-backup/<time>/<dest>` for a
+    /// project path and `~/.copm/backups/<time>/<path under home>` for a global one, so
+    /// that no assistant finds a backed-up skill or agent and loads it a second time.
+    fn backup_path(&mut self, dest: &Path) -> Result<PathBuf, CopmError> {
+        let time = *self.backup_time.get_or_insert_with(now);
+        let (root, relative) = match &self.backup_dir {
+            Some(dir) => (dir.clone(), dest),
+            None if dest.is_relative() => (paths::local_backup_dir().join(time.to_string()), dest),
+            None => {
+                let home = paths::home()?;
+                let relative = dest.strip_prefix(&home).unwrap_or(dest);
+                (paths::global_backup_dir()?.join(time.to_string()), relative)
+            }
+        };
+        let relative: PathBuf = relative
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+
+        let base = root.join(relative);
+        let mut backup = base.clone();
+        let mut n = 1;
+        while backup.symlink_metadata().is_ok() {
+            backup = PathBuf::from(format!("{}.{n}", base.display()));
+            n += 1;
+        }
+        self.create_parent(&backup)?;
+        Ok(backup)
+    }
+
+    /// Create the parent directories of `path`, removing them again on rollback.
+    /// Returns the parent.
+    fn create_parent(&mut self, path: &Path) -> Result<PathBuf, CopmError> {
+        let parent = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+
+        if !parent.exists() {
+            let created = parent
+                .ancestors()
+                .take_while(|a| !a.as_os_str().is_empty() && !a.exists())
+                .last()
+                .map(Path::to_path_buf);
+            std::fs::create_dir_all(parent)?;
+            if let Some(created) = created {
+                self.undo.push(Undo::Remove(created));
+            }
+        }
+        Ok(parent.to_path_buf())
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.done {
+            self.revert();
+        }
+    }
+}
+
+/// Delete a file or directory; a missing one is fine.
+fn remove(path: &Path) -> std::io::Result<()> {
+    let result = match path.symlink_metadata() {
+        Ok(m) if m.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
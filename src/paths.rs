@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use crate::error::CopmError;
 
-fn home() -> Result<PathBuf, CopmError> {
+pub(crate) fn home() -> Result<PathBuf, CopmError> {
     dirs::home_dir().ok_or_else(|| {
         CopmError::Io(std::io::Error::new(
             std::io::ErrorKind::NotFound,
@@ -30,6 +30,35 @@ pub fn global_copm_dir() -> Result<PathBuf, CopmError> {
     Ok(home()?.join(".copm"))
 }
 
+/// Per-user settings (tokens etc.): ~/.copm/config.json
+pub fn user_config_path() -> Result<PathBuf, CopmError> {
+    Ok(global_copm_dir()?.join("config.json"))
+}
+
+/// Owners of files installed globally or into projects without copm.lock: ~/.copm/installed.json
+pub fn installed_files_path() -> Result<PathBuf, CopmError> {
+    Ok(global_copm_dir()?.join("installed.json"))
+}
+
+/// Held by global installs and cache changes (shared by project installs): ~/.copm/locks/global.lock
+pub fn global_process_lock_path() -> Result<PathBuf, CopmError> {
+    Ok(global_copm_dir()?.join("locks").join("global.lock"))
+}
+
+
+/// Where `copm install -g --force` keeps the files it replaced: ~/.copm/backups/
+pub fn global_backup_dir() -> Result<PathBuf, CopmError> {
+    Ok(global_copm_dir()?.join("backups"))
+}
+
+/// Download cache: ~/.copm/cache/ (or $COPM_CACHE_DIR)
+pub fn cache_dir() -> Result<PathBuf, CopmError> {
+    match std::env::var_os("COPM_CACHE_DIR") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => Ok(global_copm_dir()?.join("cache")),
+    }
+}
+
 /// Path to copm.json in the current directory
 pub fn copm_json_path() -> PathBuf {
     PathBuf::from("copm.json")
@@ -40,6 +69,16 @@ pub fn copm_lock_path() -> PathBuf {
     PathBuf::from("copm.lock")
 }
 
+/// Held while a command changes the project in the current directory: .copm.lock.pid
+pub fn process_lock_path() -> PathBuf {
+    PathBuf::from(".copm.lock.pid")
+}
+
+/// Where `copm install --force` keeps the files it replaced in the project: .copm-backup/
+pub fn local_backup_dir() -> PathBuf {
+    PathBuf::from(".copm-backup")
+}
+
 // ── Copilot local paths ───────────────────────────────────────────────────────
 
 /// Path to .github/copilot-instructions.md
@@ -107,3 +146,30 @@ pub fn global_claude_skills_dir(name: &str) -> Result<PathBuf, CopmError> {
 pub fn global_claude_commands_dir() -> Result<PathBuf, CopmError> {
     Ok(home()?.join(".claude").join("commands"))
 }
+
+// ── Managed roots ─────────────────────────────────────────────────────────────
+
+/// Every directory copm installs into (plus `.github/copilot-instructions.md`).
+/// Uninstall only deletes paths inside one of these.
+pub fn managed_roots() -> Vec<PathBuf> {
+    let mut roots = vec![
+        copilot_instructions_path(),
+        copilot_custom_instructions_dir(),
+        copilot_agents_dir(),
+        copilot_prompts_dir(),
+        PathBuf::from(".github").join("skills"),
+        PathBuf::from(".claude").join("skills"),
+        local_claude_commands_dir(),
+        PathBuf::from(".claude").join("plugins"),
+    ];
+    if let Ok(home) = home() {
+        roots.extend([
+            home.join(".copilot").join("instructions"),
+            home.join(".copilot").join("skills"),
+            home.join(".claude").join("skills"),
+            home.join(".claude").join("commands"),
+            home.join(".claude").join("plugins").join("copm-packages"),
+        ]);
+    }
+    roots
+}